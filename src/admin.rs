@@ -0,0 +1,466 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_files::NamedFile;
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    audit::{self, Event, Record},
+    auth::Identity,
+    clean, config,
+    deletion::{self, DeletionError},
+    detect,
+    meta::{self, FileMeta},
+    metrics,
+    ratelimit::ClientIp,
+    webhooks::{self, WebhookEvent},
+};
+
+pub const MAX_PER_PAGE: usize = 500;
+
+#[derive(Debug, thiserror::Error, actix_web_error::Json)]
+pub enum AdminError {
+    #[error("This file doesn't exist")]
+    #[status(404)]
+    NotFound,
+    #[error("Internal error: {0}")]
+    #[status(500)]
+    Io(io::Error),
+}
+
+/// Filters for listing uploads, all of them are optional.
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// Starts at 1.
+    #[serde(default = "default_page")]
+    page: usize,
+    #[serde(default = "default_per_page")]
+    per_page: usize,
+    /// An extension (`png`) or a MIME type (`image/png`, `image/*`).
+    #[serde(rename = "type")]
+    type_: Option<String>,
+    /// In bytes.
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// Only uploads that were modified longer ago than this (e.g. `30d`).
+    #[serde(default, deserialize_with = "config::some_humantime")]
+    min_age: Option<Duration>,
+    #[serde(default, deserialize_with = "config::some_humantime")]
+    max_age: Option<Duration>,
+    /// The name of the key or token used to upload the file.
+    uploader: Option<String>,
+    /// A case-insensitive part of the filename.
+    name: Option<String>,
+}
+
+impl ListQuery {
+    /// Whether an upload passes the filters, `uploader_files` are the uploads of `uploader`.
+    fn matches(
+        &self,
+        upload: &Entry,
+        uploader_files: Option<&HashSet<String>>,
+        now: SystemTime,
+    ) -> bool {
+        let age = now.duration_since(upload.modified).unwrap_or_default();
+        uploader_files.is_none_or(|files| files.contains(&upload.name))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|part| upload.name.to_lowercase().contains(&part.to_lowercase()))
+            && self
+                .type_
+                .as_ref()
+                .is_none_or(|pattern| detect::matches_type(pattern, extension(&upload.name)))
+            && self.min_size.is_none_or(|min| upload.size >= min)
+            && self.max_size.is_none_or(|max| upload.size <= max)
+            && self.min_age.is_none_or(|min| age >= min)
+            && self.max_age.is_none_or(|max| age <= max)
+    }
+}
+
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+pub fn default_page() -> usize {
+    1
+}
+
+pub fn default_per_page() -> usize {
+    50
+}
+
+/// The entries of a page, starting at 1.
+pub fn paginate<T>(entries: Vec<T>, page: usize, per_page: usize) -> impl Iterator<Item = T> {
+    let skip = page.saturating_sub(1).saturating_mul(per_page);
+    entries.into_iter().skip(skip).take(per_page)
+}
+
+#[derive(Serialize)]
+pub struct Upload {
+    name: String,
+    link: String,
+    content_type: String,
+    size: u64,
+    /// Unix timestamp in seconds.
+    modified: u64,
+    #[serde(flatten)]
+    meta: FileMeta,
+}
+
+#[derive(Serialize)]
+pub struct ListResponse {
+    /// The number of uploads matching the filters.
+    total: usize,
+    page: usize,
+    per_page: usize,
+    /// Newest first.
+    uploads: Vec<Upload>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    files: usize,
+    /// The size of all uploads in bytes.
+    size: u64,
+    /// Unix timestamp of the oldest upload.
+    oldest: Option<u64>,
+    /// Uploads by key, uploads without an uploader have no name.
+    keys: Vec<KeyStats>,
+    retention: RetentionStats,
+}
+
+#[derive(Serialize)]
+pub struct KeyStats {
+    name: Option<String>,
+    files: usize,
+    size: u64,
+    last_upload: u64,
+}
+
+#[derive(Serialize)]
+pub struct RetentionStats {
+    /// Uploads older than this (in seconds) are removed by `clean`.
+    max_age: u64,
+    /// Uploads that will be removed by the next `clean`.
+    expired_files: usize,
+    expired_size: u64,
+}
+
+#[derive(Deserialize)]
+pub struct BulkDelete {
+    files: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkDeleteResponse {
+    deleted: Vec<String>,
+    /// Files that couldn't be deleted and the reason.
+    errors: BTreeMap<String, String>,
+}
+
+/// The dashboard, it uses the API with the authorization from the home page.
+#[get("/admin")]
+pub async fn dashboard() -> impl Responder {
+    NamedFile::open_async("./static/pages/admin/admin.html").await
+}
+
+#[get("/uploads")]
+pub async fn list(query: web::Query<ListQuery>) -> Result<HttpResponse, AdminError> {
+    let per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+    let now = SystemTime::now();
+
+    let uploader_files = match &query.uploader {
+        Some(uploader) => Some(meta::uploads_of(uploader).await.map_err(AdminError::Io)?),
+        None => None,
+    };
+
+    let mut uploads = read_uploads().await.map_err(AdminError::Io)?;
+    uploads.retain(|upload| query.matches(upload, uploader_files.as_ref(), now));
+    uploads.sort_unstable_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    // Only the metadata of the page is read.
+    let total = uploads.len();
+    let mut page = Vec::with_capacity(per_page);
+    for entry in paginate(uploads, query.page, per_page) {
+        let meta = read_meta(&entry.name).await;
+        page.push(upload(entry.name, entry.size, entry.modified, meta));
+    }
+
+    Ok(HttpResponse::Ok().json(ListResponse {
+        total,
+        page: query.page,
+        per_page,
+        uploads: page,
+    }))
+}
+
+/// Disk usage, uploads per key and the status of the retention.
+#[get("/stats")]
+pub async fn stats() -> Result<HttpResponse, AdminError> {
+    let now = SystemTime::now();
+    let retention = clean::retention();
+
+    let mut stats = StatsResponse {
+        files: 0,
+        size: 0,
+        oldest: None,
+        keys: Vec::new(),
+        retention: RetentionStats {
+            max_age: retention.as_secs(),
+            expired_files: 0,
+            expired_size: 0,
+        },
+    };
+    let mut keys = BTreeMap::<Option<String>, KeyStats>::new();
+    for entry in read_uploads().await.map_err(AdminError::Io)? {
+        let modified = timestamp(entry.modified);
+        stats.files += 1;
+        stats.size += entry.size;
+        stats.oldest = Some(stats.oldest.map_or(modified, |o: u64| o.min(modified)));
+        if now.duration_since(entry.modified).unwrap_or_default() > retention {
+            stats.retention.expired_files += 1;
+            stats.retention.expired_size += entry.size;
+        }
+
+        let uploader = read_meta(&entry.name).await.uploader;
+        let key = keys.entry(uploader.clone()).or_insert_with(|| KeyStats {
+            name: uploader,
+            files: 0,
+            size: 0,
+            last_upload: 0,
+        });
+        key.files += 1;
+        key.size += entry.size;
+        key.last_upload = key.last_upload.max(modified);
+    }
+    stats.keys = keys.into_values().collect();
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[get("/uploads/{filename}")]
+pub async fn detail(filename: web::Path<String>) -> Result<HttpResponse, AdminError> {
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return Err(AdminError::NotFound);
+    }
+    let metadata = match tokio::fs::metadata(config::get().file_dir.join(&*filename)).await {
+        Ok(m) if m.is_file() => m,
+        Ok(_) => return Err(AdminError::NotFound),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(AdminError::NotFound),
+        Err(e) => return Err(AdminError::Io(e)),
+    };
+    let meta = read_meta(&filename).await;
+    Ok(HttpResponse::Ok().json(upload(
+        filename.into_inner(),
+        metadata.len(),
+        metadata.modified().unwrap_or(UNIX_EPOCH),
+        meta,
+    )))
+}
+
+/// Deletes uploads without their deletion keys.
+#[delete("/uploads")]
+pub async fn bulk_delete(
+    body: web::Json<BulkDelete>,
+    identity: web::ReqData<Identity>,
+    ClientIp(ip): ClientIp,
+) -> HttpResponse {
+    let mut deleted = Vec::new();
+    let mut errors = BTreeMap::new();
+    for file in body.into_inner().files {
+        if file.starts_with('.') || file.contains(['/', '\\']) {
+            errors.insert(file, DeletionError::NotFound.to_string());
+            continue;
+        }
+        let (size, hash) = audit::file_info(&file).await;
+        let res = deletion::remove(&file).await;
+        audit::log(Record {
+            key: Some(identity.name.clone()),
+            ip,
+            file: Some(file.clone()),
+            size,
+            hash: hash.clone(),
+            ..Record::new(
+                Event::AdminDeletion,
+                res.as_ref().map_or_else(|e| e.kind(), |_| "ok"),
+            )
+        });
+        match res {
+            Ok(()) => {
+                metrics::deletion("admin");
+                info!(key = identity.name, file, "Deleted upload");
+                webhooks::send(webhooks::Event {
+                    key: Some(identity.name.clone()),
+                    size,
+                    hash,
+                    ..webhooks::Event::new(WebhookEvent::Deletion, file.clone())
+                })
+                .await;
+                deleted.push(file);
+            }
+            Err(e) => {
+                metrics::deletion_error(e.kind());
+                errors.insert(file, e.to_string());
+            }
+        }
+    }
+    HttpResponse::Ok().json(BulkDeleteResponse { deleted, errors })
+}
+
+/// The name, size and modification time of all uploads.
+pub async fn read_uploads() -> io::Result<Vec<Entry>> {
+    let mut uploads = Vec::new();
+    let mut dir = tokio::fs::read_dir(&config::get().file_dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        // hidden entries (like the metadata directory) aren't uploads
+        if name.starts_with('.') {
+            continue;
+        }
+        match entry.metadata().await {
+            Ok(m) if m.is_file() => uploads.push(Entry {
+                name,
+                size: m.len(),
+                modified: m.modified().unwrap_or(UNIX_EPOCH),
+            }),
+            Ok(_) => (),
+            Err(e) => warn!(error = ?e, file = name, "Couldn't read file metadata"),
+        }
+    }
+    Ok(uploads)
+}
+
+fn upload(name: String, size: u64, modified: SystemTime, meta: FileMeta) -> Upload {
+    Upload {
+        link: format!("{}/{name}", config::get().raw_domain()),
+        content_type: mime_guess::from_ext(extension(&name))
+            .first_or_octet_stream()
+            .to_string(),
+        size,
+        modified: timestamp(modified),
+        meta,
+        name,
+    }
+}
+
+pub fn timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn extension(name: &str) -> &str {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+}
+
+pub async fn read_meta(filename: &str) -> FileMeta {
+    match meta::read(filename).await {
+        Ok(meta) => meta.unwrap_or_default(),
+        Err(e) => {
+            warn!(error = ?e, file = filename, "Couldn't read metadata");
+            FileMeta::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query: &str) -> ListQuery {
+        web::Query::<ListQuery>::from_query(query)
+            .unwrap()
+            .into_inner()
+    }
+
+    fn entry(name: &str, size: u64, age_days: u64, now: SystemTime) -> Entry {
+        Entry {
+            name: name.to_owned(),
+            size,
+            modified: now - Duration::from_secs(age_days * 24 * 60 * 60),
+        }
+    }
+
+    #[test]
+    fn parses_filters() {
+        let q = query("type=image/*&max_age=7d&min_age=1h&min_size=10&page=2&uploader=sharex");
+        assert_eq!(q.type_.as_deref(), Some("image/*"));
+        assert_eq!(q.max_age, Some(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(q.min_age, Some(Duration::from_secs(60 * 60)));
+        assert_eq!(q.min_size, Some(10));
+        assert_eq!(q.max_size, None);
+        assert_eq!(q.uploader.as_deref(), Some("sharex"));
+        assert_eq!((q.page, q.per_page), (2, default_per_page()));
+
+        let q = query("");
+        assert_eq!((q.page, q.per_page), (1, 50));
+        assert_eq!(q.max_age, None);
+
+        for invalid in ["max_age=7", "max_age=soon", "min_size=-1", "page=first"] {
+            assert!(
+                web::Query::<ListQuery>::from_query(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn filters_uploads() {
+        let now = SystemTime::now();
+        let png = entry("Cat.png", 100, 1, now);
+        let jpg = entry("dog.jpg", 2000, 10, now);
+        let mp3 = entry("cat.mp3", 10, 3, now);
+        let names = |q: &str, files: Option<&HashSet<String>>| {
+            let q = query(q);
+            [&png, &jpg, &mp3]
+                .into_iter()
+                .filter(|e| q.matches(e, files, now))
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("", None), ["Cat.png", "dog.jpg", "cat.mp3"]);
+        assert_eq!(names("type=image/*", None), ["Cat.png", "dog.jpg"]);
+        assert_eq!(names("type=png", None), ["Cat.png"]);
+        assert_eq!(names("type=audio/mpeg", None), ["cat.mp3"]);
+        assert_eq!(names("max_age=7d", None), ["Cat.png", "cat.mp3"]);
+        assert_eq!(names("min_age=2d", None), ["dog.jpg", "cat.mp3"]);
+        assert_eq!(names("min_size=50&max_size=1000", None), ["Cat.png"]);
+        assert_eq!(names("name=CAT", None), ["Cat.png", "cat.mp3"]);
+        assert_eq!(names("type=image/*&max_age=7d", None), ["Cat.png"]);
+        let files = HashSet::from(["dog.jpg".to_owned(), "cat.mp3".to_owned()]);
+        assert_eq!(names("", Some(&files)), ["dog.jpg", "cat.mp3"]);
+        assert_eq!(names("", Some(&HashSet::new())), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn paginates() {
+        let page = |page, per_page| {
+            paginate((1..=5).collect::<Vec<u32>>(), page, per_page).collect::<Vec<_>>()
+        };
+        assert_eq!(page(1, 2), [1, 2]);
+        // Pages start at 1, but 0 is the first page too.
+        assert_eq!(page(0, 2), [1, 2]);
+        assert_eq!(page(3, 2), [5]);
+        assert!(page(4, 2).is_empty());
+        assert!(page(usize::MAX, MAX_PER_PAGE).is_empty());
+    }
+}
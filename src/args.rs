@@ -1,9 +1,28 @@
-use crate::clean;
+use std::{io::BufRead, net::IpAddr, path::PathBuf};
+
+use base64::Engine;
+
+use crate::{
+    audit::{self, Event},
+    auth::{self, Limits, Scope},
+    clean, config,
+    init::{self, InitOptions},
+    secrets, token,
+};
 
 /// If no subcommand is specified, the server will run.
 #[derive(clap::Parser)]
 #[command(author, version, about)]
 pub struct Args {
+    /// The config file.
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "UPLOADER_CONFIG",
+        default_value = "config.toml"
+    )]
+    pub config: PathBuf,
     #[command(subcommand)]
     pub cmd: Option<Command>,
 }
@@ -12,9 +31,9 @@ pub struct Args {
 pub enum Command {
     /// Clean the oldest files.
     Clean {
-        /// Files older than this age will be removed.
-        #[arg(short = 'a', long, default_value = "1y")]
-        max_age: humantime::Duration,
+        /// Files older than this age will be removed [default: `retention` from the config or 1y]
+        #[arg(short = 'a', long)]
+        max_age: Option<humantime::Duration>,
         /// Don't remove files, print them to stdout.
         #[arg(long, default_value_t = false)]
         dry_run: bool,
@@ -22,6 +41,83 @@ pub enum Command {
         #[arg(short = 'm', long, default_value = "modified")]
         metric: FileMetric,
     },
+    /// Print the hash of a key for the `keys` in the config.
+    HashKey {
+        /// The full value of the `Authorization` header (e.g. "Bearer <token>").
+        /// It's read from stdin if it's not specified.
+        key: Option<String>,
+    },
+    /// Issue a token signed with the secret.
+    Token {
+        /// Uploads are attributed to this name.
+        #[arg(short, long)]
+        name: String,
+        /// What the token can be used for.
+        #[arg(short, long = "scope", value_delimiter = ',', default_value = "upload")]
+        scopes: Vec<Scope>,
+        /// How long the token is valid.
+        #[arg(short, long, default_value = "1d")]
+        expires: humantime::Duration,
+        /// The maximum size of a single upload in bytes.
+        #[arg(long)]
+        max_size: Option<u64>,
+        /// Allowed extensions (`png`) or MIME types (`image/png`, `image/*`).
+        #[arg(short, long = "type", value_delimiter = ',')]
+        types: Vec<String>,
+    },
+    /// Print the records of the audit log as JSON lines, oldest first.
+    Audit {
+        /// Only records of this event.
+        #[arg(short, long)]
+        event: Option<Event>,
+        /// Only records of this key or token.
+        #[arg(short, long)]
+        key: Option<String>,
+        /// Only records from this client IP.
+        #[arg(long)]
+        ip: Option<IpAddr>,
+        /// Only records of this file.
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Only records newer than this (e.g. `7d`).
+        #[arg(short, long)]
+        since: Option<humantime::Duration>,
+    },
+    /// Create a config with a new secret and key.
+    Init {
+        /// The public URL of the instance (e.g. `https://i.example.com`).
+        #[arg(long)]
+        domain: Option<String>,
+        /// The address to listen on.
+        #[arg(long)]
+        bind: Option<String>,
+        /// The directory for uploads.
+        #[arg(long)]
+        file_dir: Option<PathBuf>,
+        /// Don't ask for values that weren't specified, use the defaults.
+        #[arg(short, long)]
+        yes: bool,
+        /// Overwrite an existing config.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Report every problem with the config, including overrides from the environment.
+    CheckConfig,
+    /// Manage the secrets used for deletion keys and tokens.
+    Secret {
+        #[command(subcommand)]
+        cmd: SecretCommand,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum SecretCommand {
+    /// Add a new secret, which will be used for new deletion keys and tokens.
+    Add,
+    /// Stop accepting deletion keys and tokens signed with a secret.
+    Retire { id: u8 },
+    /// List the IDs of all secrets.
+    List,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
@@ -48,7 +144,76 @@ impl Command {
                 max_age,
                 dry_run,
                 metric,
-            } => clean::clean(**max_age, *dry_run, *metric),
+            } => clean::clean(
+                max_age.map_or_else(clean::retention, Into::into),
+                *dry_run,
+                *metric,
+            ),
+            Command::HashKey { key } => {
+                let key = match key {
+                    Some(key) => key.clone(),
+                    None => {
+                        let mut line = String::new();
+                        std::io::stdin().lock().read_line(&mut line)?;
+                        line.trim_end_matches(['\r', '\n']).to_owned()
+                    }
+                };
+                let hash = auth::hash_key(key.as_bytes());
+                println!("{}", base64::engine::general_purpose::STANDARD.encode(hash));
+                Ok(())
+            }
+            Command::Token {
+                name,
+                scopes,
+                expires,
+                max_size,
+                types,
+            } => {
+                if config::get().keys.iter().any(|k| k.name == *name) {
+                    return Err(std::io::Error::other(format!(
+                        "{name:?} is the name of a key, tokens need their own names"
+                    )));
+                }
+                let limits = Limits {
+                    max_size: *max_size,
+                    types: (!types.is_empty()).then(|| types.clone()),
+                };
+                let token = token::issue(name.clone(), scopes.clone(), **expires, limits);
+                println!("Bearer {token}");
+                Ok(())
+            }
+            Command::Audit {
+                event,
+                key,
+                ip,
+                file,
+                since,
+            } => audit::print(&audit::Filter {
+                event: *event,
+                key: key.clone(),
+                ip: *ip,
+                file: file.clone(),
+                since: since.map(Into::into),
+            }),
+            Command::Init {
+                domain,
+                bind,
+                file_dir,
+                yes,
+                force,
+            } => init::init(InitOptions {
+                domain: domain.clone(),
+                bind: bind.clone(),
+                file_dir: file_dir.clone(),
+                yes: *yes,
+                force: *force,
+            }),
+            Command::CheckConfig => config::check(),
+            Command::Secret { cmd } => match cmd {
+                SecretCommand::Add => secrets::add(),
+                SecretCommand::Retire { id } => secrets::retire(*id),
+                SecretCommand::List => secrets::list(),
+            },
         }
     }
 }
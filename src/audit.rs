@@ -0,0 +1,315 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{config, meta};
+
+static LOG: Lazy<Mutex<Option<LogFile>>> = Lazy::new(Default::default);
+/// Records are written on a separate thread, so the server doesn't block on the file.
+static WRITER: Lazy<mpsc::Sender<Record>> = Lazy::new(|| {
+    let (tx, rx) = mpsc::channel::<Record>();
+    std::thread::Builder::new()
+        .name("audit-log".to_owned())
+        .spawn(move || rx.into_iter().for_each(write))
+        .expect("Couldn't start the audit log writer");
+    tx
+});
+
+/// The open log, which is reopened if the path changes or the file is replaced (e.g. rotated by `clean`).
+struct LogFile {
+    path: PathBuf,
+    id: Option<u64>,
+    file: File,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            id: file_id(&file.metadata()?),
+            file,
+        })
+    }
+}
+
+/// The `[audit]` section of the config, the log is disabled if there's no `path`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// A JSON-lines file, records are appended to it.
+    pub path: Option<PathBuf>,
+    /// The size in bytes after which the log is rotated.
+    pub max_size: u64,
+    /// How many rotated logs are kept (`audit.jsonl.1`, `audit.jsonl.2`, ...).
+    pub max_files: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Upload,
+    /// A deletion with a deletion link.
+    Deletion,
+    /// A deletion through the admin API.
+    AdminDeletion,
+    /// A removal by the `clean` subcommand.
+    Expiry,
+    AuthFailure,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// RFC 3339.
+    pub time: String,
+    pub event: Event,
+    /// The name of the key or token used for the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The hex SHA-256 of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The type claimed by the extension or `Content-Type` of an upload if it didn't match the
+    /// content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_type: Option<String>,
+    /// The MIME type of the content if it didn't match `claimed_type`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_type: Option<String>,
+    /// `ok` or the kind of the error.
+    pub outcome: String,
+}
+
+impl Record {
+    pub fn new(event: Event, outcome: &str) -> Self {
+        Self {
+            time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            event,
+            key: None,
+            ip: None,
+            file: None,
+            size: None,
+            hash: None,
+            claimed_type: None,
+            detected_type: None,
+            outcome: outcome.to_owned(),
+        }
+    }
+}
+
+/// Appends a record to the log if it's enabled.
+pub fn log(record: Record) {
+    if config::get().audit.path.is_some() {
+        // The writer only stops with the process.
+        let _ = WRITER.send(record);
+    }
+}
+
+/// Blocking version of [`log`] for use in subcommands, which could exit before the writer is done.
+pub fn log_sync(record: Record) {
+    write(record);
+}
+
+fn write(record: Record) {
+    let config = config::get();
+    let Some(path) = &config.audit.path else {
+        return;
+    };
+    if let Err(e) = append(&config.audit, path, &record) {
+        warn!(error = ?e, "Couldn't write audit log");
+    }
+}
+
+fn append(config: &AuditConfig, path: &Path, record: &Record) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let mut log = LOG.lock().unwrap();
+    let current = match fs::metadata(path) {
+        Ok(m) => Some(m),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if log
+        .as_ref()
+        .is_some_and(|l| l.path != path || current.as_ref().map(file_id) != Some(l.id))
+    {
+        *log = None;
+    }
+    let len = current.map_or(0, |m| m.len());
+    if len > 0 && len + line.len() as u64 > config.max_size {
+        *log = None;
+        rotate(config, path)?;
+    }
+    let log = match &mut *log {
+        Some(log) => log,
+        None => log.insert(LogFile::open(path)?),
+    };
+    log.file.write_all(&line)
+}
+
+/// Identifies the file behind a path, this is only available on Unix.
+fn file_id(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    return Some(std::os::unix::fs::MetadataExt::ino(metadata));
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+fn rotate(config: &AuditConfig, path: &Path) -> io::Result<()> {
+    if config.max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for i in (1..config.max_files).rev() {
+        match fs::rename(rotated(path, i), rotated(path, i + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+    }
+    fs::rename(path, rotated(path, 1))
+}
+
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(format!(".{n}"));
+    name.into()
+}
+
+/// The size and hash of an upload for records of deletions.
+pub async fn file_info(filename: &str) -> (Option<u64>, Option<String>) {
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return (None, None);
+    }
+    let size = tokio::fs::metadata(config::get().file_dir.join(filename))
+        .await
+        .ok()
+        .map(|m| m.len());
+    let hash = meta::read(filename)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|m| m.sha256);
+    (size, hash)
+}
+
+/// Which records the `audit` subcommand prints.
+pub struct Filter {
+    pub event: Option<Event>,
+    pub key: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub file: Option<String>,
+    pub since: Option<Duration>,
+}
+
+impl Filter {
+    fn matches(&self, record: &Record, since: Option<SystemTime>) -> bool {
+        self.event.is_none_or(|e| e == record.event)
+            && self
+                .key
+                .as_ref()
+                .is_none_or(|k| record.key.as_ref() == Some(k))
+            && self.ip.is_none_or(|ip| record.ip == Some(ip))
+            && self
+                .file
+                .as_ref()
+                .is_none_or(|f| record.file.as_ref() == Some(f))
+            && since.is_none_or(|since| {
+                humantime::parse_rfc3339(&record.time).is_ok_and(|time| time >= since)
+            })
+    }
+}
+
+/// Prints the matching records of all logs, oldest first.
+pub fn print(filter: &Filter) -> io::Result<()> {
+    let Some(path) = &config::get().audit.path else {
+        return Err(io::Error::other("The audit log isn't enabled"));
+    };
+    let since = filter.since.map(|d| SystemTime::now() - d);
+    let files = (1..=config::get().audit.max_files)
+        .rev()
+        .map(|n| rotated(path, n))
+        .chain([path.clone()]);
+
+    let mut stdout = io::stdout().lock();
+    for file in files {
+        let file = match File::open(&file) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) if filter.matches(&record, since) => writeln!(stdout, "{line}")?,
+                Ok(_) => (),
+                Err(e) => eprintln!("Skipping invalid record: {e}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reopens_replaced_log() {
+        let dir = std::env::temp_dir().join(format!("uploader-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = AuditConfig {
+            max_size: 100,
+            max_files: 1,
+            ..Default::default()
+        };
+        let path = dir.join("audit.jsonl");
+        let other = dir.join("other.jsonl");
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+
+        append(&config, &path, &Record::new(Event::Upload, "ok")).unwrap();
+        // Rotated by another process.
+        fs::rename(&path, rotated(&path, 1)).unwrap();
+        append(&config, &path, &Record::new(Event::Upload, "ok")).unwrap();
+        assert_eq!((lines(&path), lines(&rotated(&path, 1))), (1, 1));
+
+        // Rotated here once it's full.
+        append(&config, &path, &Record::new(Event::Deletion, "ok")).unwrap();
+        assert_eq!((lines(&path), lines(&rotated(&path, 1))), (1, 1));
+        assert!(fs::read_to_string(&path).unwrap().contains("deletion"));
+
+        // A new path after a reload.
+        append(&config, &other, &Record::new(Event::Upload, "ok")).unwrap();
+        assert_eq!((lines(&path), lines(&other)), (1, 1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,121 @@
-use std::future::{ready, Ready};
+use std::{
+    fmt,
+    future::{ready, Ready},
+};
 
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderValue,
+    HttpMessage,
+};
 use constant_time_eq::constant_time_eq;
 use futures::future::Either;
+use hmac::digest::{Digest, Output};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 
+use crate::{
+    audit::{self, Event, Record},
+    config::{self, ApiKey, Config},
+    detect, lockout,
+    ratelimit::{self, Limit, Subject},
+    token::{self, Claims, TokenError},
+};
+
+pub type KeyHash = Output<Sha256>;
+
+/// Requires one of the configured keys or a token with the scope.
+///
+/// The [`Identity`] of the request is added to the request extensions.
 pub struct AuthRequirement {
-    key: &'static str,
+    scope: Option<Scope>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Upload files.
+    Upload,
+    /// Delete files without their deletion key.
+    DeleteAny,
+    /// Manage the instance.
+    Admin,
+    /// List the uploads of other keys and tokens.
+    ReadPrivate,
+    /// Read the Prometheus metrics.
+    Metrics,
+}
+
+impl Scope {
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::Upload,
+            Self::DeleteAny,
+            Self::Admin,
+            Self::ReadPrivate,
+            Self::Metrics,
+        ]
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Upload => "upload",
+            Self::DeleteAny => "delete-any",
+            Self::Admin => "admin",
+            Self::ReadPrivate => "read-private",
+            Self::Metrics => "metrics",
+        })
+    }
+}
+
+/// Restrictions on uploads of a key or token.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Limits {
+    /// The maximum size of a single upload in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Allowed extensions (`png`) or MIME types (`image/png`, `image/*`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub types: Option<Vec<String>>,
+}
+
+impl Limits {
+    pub fn allows_type(&self, extension: &str) -> bool {
+        let Some(types) = &self.types else {
+            return true;
+        };
+        types.iter().any(|t| detect::matches_type(t, extension))
+    }
+}
+
+/// The key or token a request was made with.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub limits: Limits,
+}
+
+impl From<&ApiKey> for Identity {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            name: key.name.clone(),
+            scopes: key.scopes.clone(),
+            limits: key.limits.clone(),
+        }
+    }
+}
+
+impl From<Claims> for Identity {
+    fn from(claims: Claims) -> Self {
+        Self {
+            name: claims.name,
+            scopes: claims.scopes,
+            limits: claims.limits,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error, actix_web_error::Json)]
@@ -16,14 +126,74 @@ pub enum AuthError {
     #[error("Invalid authorization")]
     #[status(401)]
     BadAuth,
+    #[error("{0}")]
+    #[status(401)]
+    Token(TokenError),
+    #[error("This requires the '{0}' scope")]
+    #[status(403)]
+    MissingScope(Scope),
+}
+
+impl AuthError {
+    /// The name of the variant for the audit log.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NoHeader => "no_header",
+            Self::BadAuth => "bad_auth",
+            Self::Token(_) => "bad_token",
+            Self::MissingScope(_) => "missing_scope",
+        }
+    }
 }
 
 impl AuthRequirement {
-    pub fn new(key: &'static str) -> Self {
-        Self { key }
+    pub fn new(scope: Scope) -> Self {
+        Self { scope: Some(scope) }
+    }
+
+    /// Any key or token, handlers check the scopes themselves.
+    pub fn authenticated() -> Self {
+        Self { scope: None }
+    }
+}
+
+pub fn hash_key(key: &[u8]) -> KeyHash {
+    Sha256::digest(key)
+}
+
+fn authenticate(header: &HeaderValue) -> Result<Identity, AuthError> {
+    let token = header
+        .to_str()
+        .ok()
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .filter(|t| t.starts_with(token::PREFIX));
+    let config = config::get();
+    match token {
+        Some(token) => {
+            let claims = token::verify(token).map_err(AuthError::Token)?;
+            // The token would be able to manage the key's uploads.
+            if config.keys.iter().any(|k| k.name == claims.name) {
+                return Err(AuthError::Token(TokenError::KeyName));
+            }
+            Ok(claims.into())
+        }
+        None => find_key(&config, header.as_bytes())
+            .map(Identity::from)
+            .ok_or(AuthError::BadAuth),
     }
 }
 
+/// Compares the hash against all keys to not leak which one matched.
+fn find_key<'a>(config: &'a Config, header: &[u8]) -> Option<&'a ApiKey> {
+    let hash = hash_key(header);
+    config.keys.iter().fold(None, |found, key| {
+        match constant_time_eq(&key.hash, &hash) {
+            true => Some(key),
+            false => found,
+        }
+    })
+}
+
 impl<S, B> Transform<S, ServiceRequest> for AuthRequirement
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
@@ -39,14 +209,14 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(Self::Transform {
             service,
-            key: self.key,
+            scope: self.scope,
         }))
     }
 }
 
 pub struct AuthMiddleware<S> {
     service: S,
-    key: &'static str,
+    scope: Option<Scope>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddleware<S>
@@ -67,10 +237,82 @@ where
             Some(h) => h,
             None => return Either::Left(ready(Err(AuthError::NoHeader.into()))),
         };
-        if constant_time_eq(header.as_bytes(), self.key.as_bytes()) {
-            Either::Right(self.service.call(req))
-        } else {
-            Either::Left(ready(Err(AuthError::BadAuth.into())))
+        // Clients that failed too often can't try again until their budget is refilled.
+        let ip = ratelimit::client_ip(req.peer_addr(), req.headers());
+        if let Some(ip) = ip {
+            if let Err(e) = lockout::check(ip) {
+                return Either::Left(ready(Err(e.into())));
+            }
+            if let Err(e) = ratelimit::check(Limit::AuthFailure, Subject::Ip(ip)) {
+                return Either::Left(ready(Err(e.into())));
+            }
+        }
+        let identity = match authenticate(header) {
+            Ok(identity) => identity,
+            Err(e) => {
+                if let Some(ip) = ip {
+                    // The error for this request is the authentication error.
+                    let _ = ratelimit::take(Limit::AuthFailure, Subject::Ip(ip));
+                    lockout::failure(ip);
+                }
+                audit::log(Record {
+                    ip,
+                    ..Record::new(Event::AuthFailure, e.kind())
+                });
+                return Either::Left(ready(Err(e.into())));
+            }
+        };
+        if let Some(ip) = ip {
+            lockout::success(ip);
+        }
+        if let Some(scope) = self.scope.filter(|s| !identity.scopes.contains(s)) {
+            return Either::Left(ready(Err(AuthError::MissingScope(scope).into())));
         }
+        req.extensions_mut().insert(identity);
+        Either::Right(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn bearer(name: &str) -> HeaderValue {
+        config::use_test_config();
+        let token = token::issue(
+            name.to_owned(),
+            vec![Scope::Upload],
+            Duration::from_secs(60),
+            Limits::default(),
+        );
+        HeaderValue::from_str(&format!("Bearer {token}")).unwrap()
+    }
+
+    #[test]
+    fn authenticates_keys_and_tokens() {
+        config::use_test_config();
+        let key = authenticate(&HeaderValue::from_static("Bearer test")).unwrap();
+        assert_eq!(key.name, "sharex");
+        // Keys without `scopes` can do everything.
+        assert_eq!(key.scopes, Scope::all());
+        assert!(key.scopes.contains(&Scope::Metrics));
+        assert_eq!(
+            authenticate(&bearer("contractor")).unwrap().name,
+            "contractor"
+        );
+        assert!(matches!(
+            authenticate(&HeaderValue::from_static("Bearer wrong")),
+            Err(AuthError::BadAuth)
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_named_like_keys() {
+        assert!(matches!(
+            authenticate(&bearer("sharex")),
+            Err(AuthError::Token(TokenError::KeyName))
+        ));
     }
 }
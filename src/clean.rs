@@ -1,12 +1,25 @@
 use std::time::Duration;
 
-use crate::{args::FileMetric, config::CONFIG};
+use crate::{
+    args::FileMetric,
+    audit::{self, Event, Record},
+    config, meta,
+    webhooks::{self, WebhookEvent},
+};
+
+/// How long uploads are kept if `clean` is run without a `--max-age`.
+pub fn retention() -> Duration {
+    // 1y (365.25 days) like humantime
+    config::get()
+        .retention
+        .unwrap_or(Duration::from_secs(31_557_600))
+}
 
 pub fn clean(duration: Duration, dry_run: bool, metric: FileMetric) -> std::io::Result<()> {
     let mut removed = 0usize;
 
     let now = std::time::SystemTime::now();
-    for entry in std::fs::read_dir(&CONFIG.file_dir)? {
+    for entry in std::fs::read_dir(&config::get().file_dir)? {
         let entry = match entry {
             Ok(e) => e,
             Err(e) => {
@@ -15,6 +28,11 @@ pub fn clean(duration: Duration, dry_run: bool, metric: FileMetric) -> std::io::
             }
         };
 
+        // hidden entries (like the metadata directory) aren't uploads
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
         let meta = match entry.metadata() {
             Ok(m) => m,
             Err(e) => {
@@ -50,12 +68,37 @@ pub fn clean(duration: Duration, dry_run: bool, metric: FileMetric) -> std::io::
                     removed += 1;
                 }
                 false => {
-                    if let Err(e) = std::fs::remove_file(entry.path()) {
+                    let filename = entry.file_name().to_string_lossy().into_owned();
+                    let hash = meta::read_sync(&filename)
+                        .ok()
+                        .flatten()
+                        .and_then(|m| m.sha256);
+                    let res = std::fs::remove_file(entry.path());
+                    audit::log_sync(Record {
+                        file: Some(filename.clone()),
+                        size: Some(meta.len()),
+                        hash: hash.clone(),
+                        ..Record::new(Event::Expiry, if res.is_ok() { "ok" } else { "io" })
+                    });
+                    if res.is_ok() {
+                        webhooks::send_sync(webhooks::Event {
+                            size: Some(meta.len()),
+                            hash,
+                            ..webhooks::Event::new(WebhookEvent::Expiry, filename)
+                        });
+                    }
+                    if let Err(e) = res {
                         eprintln!(
                             "Failed to remove {}: {e}",
                             entry.file_name().to_string_lossy()
                         );
                     } else {
+                        if let Err(e) = meta::remove_sync(&entry.file_name().to_string_lossy()) {
+                            eprintln!(
+                                "Failed to remove metadata of {}: {e}",
+                                entry.file_name().to_string_lossy()
+                            );
+                        }
                         removed += 1;
                     }
                 }
@@ -1,37 +1,526 @@
-use std::{fmt::Formatter, path::PathBuf};
+use std::{
+    collections::HashSet,
+    fmt::Formatter,
+    io,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
+use actix_web::http::Uri;
 use base64::Engine;
 use hmac::{
     digest::{array::Array, common::KeySizeUser, Key},
     Hmac,
 };
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{
     de::{Error, Visitor},
     Deserialize, Deserializer,
 };
 use sha2::Sha224;
+use tracing::{info, warn};
+
+use crate::{
+    audit::AuditConfig,
+    auth::{self, KeyHash, Limits, Scope},
+    cors::CorsConfig,
+    detect::MismatchPolicy,
+    listen::{self, Listener},
+    lockout::LockoutConfig,
+    metrics::MetricsAccess,
+    ratelimit::RateLimitConfig,
+    raw::ActiveContentPolicy,
+    shutdown,
+    svg::SvgPolicy,
+    tls::{self, TlsConfig},
+    webhooks::Webhook,
+};
+
+pub type Secret = Key<Hmac<Sha224>>;
 
-type Secret = Key<Hmac<Sha224>>;
+/// The length of secrets in bytes.
+pub const SECRET_LEN: usize = 28;
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub bind: String,
+    #[serde(deserialize_with = "listen::one_or_many")]
+    pub bind: Vec<Listener>,
     pub file_dir: PathBuf,
     pub domain: String,
-    pub authorization: String,
+    /// A single unnamed key, prefer `keys`.
+    pub authorization: Option<String>,
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+    /// The secret with the ID 0, prefer `secrets`.
+    #[serde(default, deserialize_with = "some_from_base64")]
+    pub secret: Option<Secret>,
+    #[serde(default)]
+    pub secrets: Vec<VersionedSecret>,
+    /// IDs of secrets that are no longer accepted.
+    #[serde(default)]
+    pub retired_secrets: Vec<u8>,
+    #[serde(default)]
+    pub type_mismatch: MismatchPolicy,
+    #[serde(default)]
+    pub active_content: ActiveContentPolicy,
+    /// A separate origin serving uploaded files (e.g. `https://files.example.com`).
+    pub user_content_domain: Option<String>,
+    #[serde(default)]
+    pub svg: SvgPolicy,
+    /// How long new deletion links are valid.
+    #[serde(default, deserialize_with = "some_humantime")]
+    pub deletion_link_expiry: Option<Duration>,
+    /// How long uploads are kept by the `clean` subcommand.
+    #[serde(default, deserialize_with = "some_humantime")]
+    pub retention: Option<Duration>,
+    #[serde(default)]
+    pub metrics: MetricsAccess,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Serve HTTPS instead of HTTP.
+    pub tls: Option<TlsConfig>,
+    /// How long running requests get to finish when the server stops.
+    #[serde(
+        default = "shutdown::default_timeout",
+        deserialize_with = "from_humantime"
+    )]
+    pub shutdown_timeout: Duration,
+}
+
+/// A named key allowed to upload files.
+#[derive(Clone, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    /// The SHA-256 hash of the `Authorization` header.
+    #[serde(deserialize_with = "hash_from_base64")]
+    pub hash: KeyHash,
+    /// All scopes by default.
+    #[serde(default = "Scope::all")]
+    pub scopes: Vec<Scope>,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+/// A secret used to sign deletion keys and tokens.
+///
+/// Signatures contain the ID of the secret, so secrets can be rotated
+/// without invalidating existing deletion links.
+#[derive(Deserialize)]
+pub struct VersionedSecret {
+    pub id: u8,
     #[serde(deserialize_with = "from_base64")]
     pub secret: Secret,
 }
 
-pub static CONFIG: Lazy<Config> = Lazy::new(|| {
-    toml::from_str(
-        &std::fs::read_to_string("config.toml")
-            .expect("A config.toml must be in the working directory"),
-    )
-    .expect("The config.toml must be valid")
+impl Config {
+    /// The origin serving uploaded files.
+    pub fn raw_domain(&self) -> &str {
+        self.user_content_domain.as_deref().unwrap_or(&self.domain)
+    }
+
+    /// The newest secret that isn't retired, used for new signatures.
+    pub fn signing_secret(&self) -> &VersionedSecret {
+        self.active_secrets()
+            .max_by_key(|s| s.id)
+            .expect("There's always an active secret")
+    }
+
+    /// Finds a secret that isn't retired.
+    pub fn secret(&self, id: u8) -> Option<&Secret> {
+        self.active_secrets()
+            .find(|s| s.id == id)
+            .map(|s| &s.secret)
+    }
+
+    pub fn is_retired(&self, id: u8) -> bool {
+        self.retired_secrets.contains(&id)
+    }
+
+    fn active_secrets(&self) -> impl Iterator<Item = &VersionedSecret> {
+        self.secrets.iter().filter(|s| !self.is_retired(s.id))
+    }
+}
+
+/// The prefix of environment variables overriding the config, nested keys are separated by `__`.
+const ENV_PREFIX: &str = "UPLOADER_";
+/// Fields whose environment variables are never parsed as TOML, so `UPLOADER_DOMAIN=true` stays a string.
+const ENV_STRING_FIELDS: &[&str] = &[
+    "file_dir",
+    "domain",
+    "authorization",
+    "secret",
+    "user_content_domain",
+    "audit.path",
+    "tls.cert",
+    "tls.key",
+    "tls.redirect",
+];
+
+static PATH: OnceCell<PathBuf> = OnceCell::new();
+
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+static CONFIG: Lazy<RwLock<Arc<Config>>> = Lazy::new(|| {
+    let config = Config::load(path()).unwrap_or_else(|e| panic!("{e}"));
+    RwLock::new(Arc::new(config))
 });
 
+/// The current config.
+///
+/// It can be swapped by [`reload`] at any time,
+/// so a request should only read it once if the values need to be consistent.
+pub fn get() -> Arc<Config> {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Loads and validates the config file again and swaps it with the current one if it's valid.
+///
+/// Settings that need a restart keep their current value.
+pub fn reload() -> Result<(), ConfigError> {
+    let mut config = Config::load(path())?;
+    let current = get();
+    for field in config.keep_restart_only(&current) {
+        warn!(
+            field,
+            "The config changed a setting that requires a restart"
+        );
+    }
+    *CONFIG.write().unwrap() = Arc::new(config);
+    info!("Reloaded config");
+    Ok(())
+}
+
+/// Reloads the config on SIGHUP or when the file changes.
+pub async fn watch() {
+    let modified = || std::fs::metadata(path()).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .inspect_err(|e| warn!(error = ?e, "Couldn't listen for SIGHUP"))
+        .ok();
+
+    loop {
+        #[cfg(unix)]
+        let signaled = tokio::select! {
+            _ = interval.tick() => false,
+            Some(_) = async { hangup.as_mut()?.recv().await } => true,
+        };
+        #[cfg(not(unix))]
+        let signaled = {
+            interval.tick().await;
+            false
+        };
+
+        let now_modified = modified();
+        if !signaled && now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        if let Err(e) = reload() {
+            warn!("Keeping the current config: {e}");
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Couldn't read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid environment variable {0}")]
+    Env(String),
+    #[error("Invalid config:{}", .0.iter().map(|p| format!("\n- {p}")).collect::<String>())]
+    Invalid(Vec<String>),
+}
+
+/// Reports every problem with the config for the `check-config` subcommand.
+pub fn check() -> io::Result<()> {
+    match Config::load(path()) {
+        Ok(_) => {
+            println!("{} is valid", path().display());
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Sets the path of the config file, this must be called before the config is used.
+pub fn set_path(path: PathBuf) {
+    let _ = PATH.set(path);
+}
+
+/// Loads `tests/config.toml` instead of the config in the working directory.
+#[cfg(test)]
+pub fn use_test_config() {
+    set_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/config.toml"));
+}
+
+pub fn path() -> &'static Path {
+    PATH.get()
+        .map_or(Path::new("config.toml"), PathBuf::as_path)
+}
+
+impl Config {
+    /// Reads the config file, applies the overrides from the environment and validates it.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut table: toml::Table = toml::from_str(&text)?;
+        let mut config: Config = match apply_env(&mut table, std::env::vars())? {
+            // Errors from the text point to the line.
+            false => toml::from_str(&text)?,
+            true => toml::Value::Table(table).try_into()?,
+        };
+
+        if let Some(authorization) = config.authorization.take() {
+            config.keys.push(ApiKey {
+                name: "default".to_owned(),
+                hash: auth::hash_key(authorization.as_bytes()),
+                scopes: Scope::all(),
+                limits: Limits::default(),
+            });
+        }
+        if let Some(secret) = config.secret.take() {
+            config.secrets.push(VersionedSecret { id: 0, secret });
+        }
+
+        match config.problems() {
+            problems if problems.is_empty() => Ok(config),
+            problems => Err(ConfigError::Invalid(problems)),
+        }
+    }
+
+    /// Replaces settings that can't be changed without a restart with the current ones
+    /// and returns their names if they changed.
+    fn keep_restart_only(&mut self, current: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.bind != current.bind {
+            changed.push("bind");
+            self.bind.clone_from(&current.bind);
+        }
+        if self.file_dir != current.file_dir {
+            changed.push("file_dir");
+            self.file_dir.clone_from(&current.file_dir);
+        }
+        if self.metrics != current.metrics {
+            changed.push("metrics");
+            self.metrics = current.metrics;
+        }
+        if self.shutdown_timeout != current.shutdown_timeout {
+            changed.push("shutdown_timeout");
+            self.shutdown_timeout = current.shutdown_timeout;
+        }
+        // The files are watched separately, see `tls::server_config`.
+        if self.tls != current.tls {
+            changed.push("tls");
+            self.tls.clone_from(&current.tls);
+        }
+        changed
+    }
+
+    /// Everything that's wrong with the config, beyond what's checked when it's parsed.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.bind.is_empty() {
+            problems.push("`bind` needs at least one listener".to_owned());
+        }
+        for listener in &self.bind {
+            match listener {
+                Listener::Tcp(address) => problems.extend(check_bind(address)),
+                Listener::Unix { path, .. } => {
+                    let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+                    if dir.is_some_and(|dir| !dir.is_dir()) {
+                        problems.push(format!(
+                            "The directory of the socket {} doesn't exist",
+                            path.display()
+                        ));
+                    }
+                }
+                Listener::Systemd => (),
+            }
+        }
+        if let Err(e) = check_writable(&self.file_dir) {
+            problems.push(format!(
+                "`file_dir` ({}) isn't writable: {e}",
+                self.file_dir.display()
+            ));
+        }
+        problems.extend(check_origin("domain", &self.domain));
+        if let Some(domain) = &self.user_content_domain {
+            problems.extend(check_origin("user_content_domain", domain));
+        } else if self.active_content == ActiveContentPolicy::Redirect {
+            problems.push(
+                "The \"redirect\" policy for active content requires a `user_content_domain`"
+                    .to_owned(),
+            );
+        }
+
+        let mut ids = HashSet::new();
+        if let Some(secret) = self.secrets.iter().find(|s| !ids.insert(s.id)) {
+            problems.push(format!("The ID {} is used by multiple secrets", secret.id));
+        }
+        if self.active_secrets().next().is_none() {
+            problems.push("At least one secret must be configured and not retired".to_owned());
+        }
+        let mut names = HashSet::new();
+        if let Some(key) = self.keys.iter().find(|k| !names.insert(k.name.as_str())) {
+            problems.push(format!("The name {:?} is used by multiple keys", key.name));
+        }
+
+        if let Some(tls) = &self.tls {
+            if let Err(e) = tls::load(tls) {
+                problems.push(format!("Invalid `[tls]` certificate: {e}"));
+            }
+            if let Some(problem) = tls.redirect.as_deref().and_then(check_bind) {
+                problems.push(format!("`tls.redirect`: {problem}"));
+            }
+        }
+        for origin in self.cors.origins.iter().filter(|o| *o != "*") {
+            problems.extend(check_origin("cors.origins", origin));
+        }
+        if self.cors.credentials && self.cors.allows_any_origin() {
+            problems.push("`cors.credentials` can't be used with any origin (\"*\")".to_owned());
+        }
+        for webhook in &self.webhooks {
+            problems.extend(check_url("webhooks.url", &webhook.url));
+        }
+        if let Some(path) = &self.audit.path {
+            let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+            if dir.is_some_and(|dir| !dir.is_dir()) {
+                problems.push(format!(
+                    "The directory of the audit log ({}) doesn't exist",
+                    path.display()
+                ));
+            }
+        }
+        problems
+    }
+}
+
+pub fn check_bind(bind: &str) -> Option<String> {
+    match bind.to_socket_addrs() {
+        Ok(_) => None,
+        Err(_) => Some(format!(
+            "`bind` must be an address like \"127.0.0.1:8080\", got {bind:?}"
+        )),
+    }
+}
+
+/// The port of a `bind` address.
+pub fn bind_port(bind: &str) -> Option<u16> {
+    bind.to_socket_addrs().ok()?.next().map(|a| a.port())
+}
+
+/// Checks that a URL is absolute and uses HTTP(S).
+pub fn check_origin(field: &str, url: &str) -> Option<String> {
+    let valid = url.parse::<Uri>().is_ok_and(|uri| {
+        matches!(uri.scheme_str(), Some("http" | "https"))
+            && uri.authority().is_some()
+            && uri.query().is_none()
+    });
+    match valid && !url.ends_with('/') {
+        true => None,
+        false => Some(format!(
+            "`{field}` must be a URL like \"https://example.com\" without a trailing slash, got {url:?}"
+        )),
+    }
+}
+
+/// Checks that a URL is absolute and uses HTTP(S), unlike origins it can have a path and query.
+pub fn check_url(field: &str, url: &str) -> Option<String> {
+    let valid = url.parse::<Uri>().is_ok_and(|uri| {
+        matches!(uri.scheme_str(), Some("http" | "https")) && uri.authority().is_some()
+    });
+    match valid {
+        true => None,
+        false => Some(format!(
+            "`{field}` must be a URL like \"https://example.com/hook\", got {url:?}"
+        )),
+    }
+}
+
+/// Creates and removes a file in the directory or the closest existing parent.
+fn check_writable(dir: &Path) -> io::Result<()> {
+    let existing = dir
+        .ancestors()
+        .find(|p| p.as_os_str().is_empty() || p.exists())
+        .unwrap_or(dir);
+    let existing = match existing.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => existing,
+    };
+    if !existing.is_dir() {
+        return Err(io::Error::other("it's not a directory"));
+    }
+    let probe = existing.join(".uploader-write-test");
+    std::fs::write(&probe, [])?;
+    std::fs::remove_file(probe)
+}
+
+/// Overrides values in the config with `UPLOADER_*` variables and returns if any were set.
+///
+/// `UPLOADER_RATE_LIMIT__UPLOAD` sets `upload` in `[rate_limit]`.
+/// Values are parsed as TOML (like `["upload"]` or `{ per_minute = 5, burst = 10 }`)
+/// and used as strings if that fails or the field is a string.
+fn apply_env(
+    table: &mut toml::Table,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<bool, ConfigError> {
+    let mut applied = false;
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        // used by the `--config` flag
+        if key == "CONFIG" {
+            continue;
+        }
+        let field = key.replace("__", ".").to_lowercase();
+        let mut path = key.split("__").map(str::to_lowercase).collect::<Vec<_>>();
+        let Some(last) = path.pop().filter(|k| !k.is_empty()) else {
+            return Err(ConfigError::Env(name));
+        };
+        let mut target = &mut *table;
+        for key in path {
+            target = match target
+                .entry(key)
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+            {
+                toml::Value::Table(t) => t,
+                _ => return Err(ConfigError::Env(name)),
+            };
+        }
+        let value = match ENV_STRING_FIELDS.contains(&field.as_str()) {
+            true => None,
+            false => toml::from_str::<toml::Table>(&format!("v = {value}"))
+                .ok()
+                .and_then(|mut t| t.remove("v")),
+        }
+        .unwrap_or(toml::Value::String(value));
+        target.insert(last, value);
+        applied = true;
+    }
+    Ok(applied)
+}
+
 fn from_base64<'de, D>(de: D) -> Result<Secret, D::Error>
 where
     D: Deserializer<'de>,
@@ -41,7 +530,7 @@ where
         type Value = Secret;
 
         fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
-            write!(f, "a base64 string")
+            write!(f, "a base64 string of {SECRET_LEN} bytes")
         }
 
         fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -49,11 +538,101 @@ where
             E: Error,
         {
             let mut arr = Array::<u8, <Hmac<Sha224> as KeySizeUser>::KeySize>::default();
-            base64::engine::general_purpose::STANDARD
+            let len = base64::engine::general_purpose::STANDARD
                 .decode_slice(v, &mut arr[..])
                 .map_err(|e| E::custom(e))?;
-            Ok(arr)
+            match len {
+                SECRET_LEN => Ok(arr),
+                _ => Err(E::invalid_length(len, &self)),
+            }
         }
     }
     de.deserialize_str(Base64Visitor)
 }
+
+fn some_from_base64<'de, D>(de: D) -> Result<Option<Secret>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    from_base64(de).map(Some)
+}
+
+pub fn from_humantime<'de, D>(de: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(de)?;
+    humantime::parse_duration(&duration).map_err(D::Error::custom)
+}
+
+pub fn some_humantime<'de, D>(de: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(de)?;
+    humantime::parse_duration(&duration)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+fn hash_from_base64<'de, D>(de: D) -> Result<KeyHash, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hash = String::deserialize(de)?;
+    let hash = base64::engine::general_purpose::STANDARD
+        .decode(hash)
+        .map_err(D::Error::custom)?;
+    KeyHash::try_from(hash.as_slice())
+        .map_err(|_| D::Error::invalid_length(hash.len(), &"a base64 SHA-256 hash"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_overrides() {
+        let mut table: toml::Table =
+            toml::from_str("bind = \"127.0.0.1:80\"\n[lockout]\nwindow = \"1m\"").unwrap();
+        let vars = [
+            ("UPLOADER_BIND", "0.0.0.0:8080"),
+            (
+                "UPLOADER_RATE_LIMIT__UPLOAD",
+                "{ per_minute = 5, burst = 10 }",
+            ),
+            ("UPLOADER_LOCKOUT__MAX_FAILURES", "3"),
+            ("UPLOADER_AUTHORIZATION", "12345"),
+            ("UPLOADER_DOMAIN", "true"),
+            ("UPLOADER_TLS__CERT", "[1]"),
+            ("UPLOADER_CONFIG", "other.toml"),
+            ("HOME", "/root"),
+        ]
+        .map(|(k, v)| (k.to_owned(), v.to_owned()));
+        assert!(apply_env(&mut table, vars.into_iter()).unwrap());
+
+        assert_eq!(table["bind"].as_str(), Some("0.0.0.0:8080"));
+        assert_eq!(
+            table["rate_limit"]["upload"]["burst"].as_integer(),
+            Some(10)
+        );
+        assert_eq!(table["lockout"]["max_failures"].as_integer(), Some(3));
+        assert_eq!(table["lockout"]["window"].as_str(), Some("1m"));
+        assert_eq!(table["authorization"].as_str(), Some("12345"));
+        assert_eq!(table["domain"].as_str(), Some("true"));
+        assert_eq!(table["tls"]["cert"].as_str(), Some("[1]"));
+        assert!(!table.contains_key("config") && !table.contains_key("home"));
+
+        let vars = [("UPLOADER_BIND__PORT".to_owned(), "80".to_owned())];
+        assert!(apply_env(&mut table, vars.into_iter()).is_err());
+    }
+
+    #[test]
+    fn webhook_urls() {
+        assert!(check_url("url", "https://example.com/hook/?token=1").is_none());
+        assert!(check_url("url", "http://example.com/").is_none());
+        assert!(check_url("url", "example.com/hook").is_some());
+        assert!(check_url("url", "ftp://example.com").is_some());
+        assert!(check_origin("origin", "https://example.com/").is_some());
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    future::{ready, Ready},
+    str::FromStr,
+    time::Duration,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method,
+    },
+    HttpResponse, ResponseError,
+};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Deserializer};
+
+use crate::config;
+
+/// The `[cors]` section of the config, by default any origin can make `GET` requests.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins like `https://example.com`, `"*"` allows any origin.
+    pub origins: Vec<String>,
+    #[serde(deserialize_with = "from_methods")]
+    pub methods: Vec<Method>,
+    /// Request headers like `authorization`.
+    #[serde(deserialize_with = "from_header_names")]
+    pub headers: Vec<HeaderName>,
+    /// Allow requests with cookies and HTTP authentication, this needs explicit `origins`.
+    pub credentials: bool,
+    /// How long browsers cache preflight responses.
+    #[serde(deserialize_with = "config::from_humantime")]
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: vec!["*".to_owned()],
+            methods: vec![Method::GET],
+            headers: vec![header::ACCEPT, header::CONTENT_TYPE],
+            credentials: false,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.allows_any_origin()
+            || self
+                .origins
+                .iter()
+                .any(|o| o.as_bytes() == origin.as_bytes())
+    }
+
+    /// Whether the headers depend on the origin, so caches have to know about it.
+    fn varies_by_origin(&self) -> bool {
+        !self.allows_any_origin() || self.credentials
+    }
+
+    fn insert_vary(&self, res: &mut HeaderMap) {
+        if self.varies_by_origin() {
+            res.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+
+    /// Adds the headers for a response to `origin`, returns whether any were added.
+    fn insert_headers(&self, res: &mut HeaderMap, origin: Option<&HeaderValue>) -> bool {
+        match origin {
+            Some(origin) if self.allows_origin(origin) => self.insert_origin(res, origin),
+            // Responses to other origins must not be cached for allowed ones.
+            _ if self.varies_by_origin() => self.insert_vary(res),
+            _ => return false,
+        }
+        true
+    }
+
+    fn insert_origin(&self, res: &mut HeaderMap, origin: &HeaderValue) {
+        if !self.varies_by_origin() {
+            res.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+            return;
+        }
+        res.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        self.insert_vary(res);
+        if self.credentials {
+            res.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, actix_web_error::Json)]
+pub enum CorsError {
+    #[error("The origin isn't allowed")]
+    #[status(403)]
+    Origin,
+    #[error("The method isn't allowed")]
+    #[status(403)]
+    Method,
+    #[error("The header {0:?} isn't allowed")]
+    #[status(403)]
+    Header(String),
+}
+
+/// Answers preflight requests and adds the CORS headers to responses, following the current config.
+///
+/// This has to wrap the whole app, so preflight requests don't reach the routes and their auth.
+pub struct Cors;
+
+impl<S, B> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = CorsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddleware { service }))
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for CorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let config = config::get();
+        let origin = req.headers().get(header::ORIGIN).cloned();
+
+        if let Some(origin) = &origin {
+            if req.method() == Method::OPTIONS
+                && req
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+            {
+                let res = preflight(&config.cors, origin, req.headers()).unwrap_or_else(|e| {
+                    let mut res = e.error_response();
+                    config.cors.insert_vary(res.headers_mut());
+                    res
+                });
+                return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+            }
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            match fut.await {
+                Ok(mut res) => {
+                    config
+                        .cors
+                        .insert_headers(res.headers_mut(), origin.as_ref());
+                    Ok(res.map_into_left_body())
+                }
+                // The response of the error needs the headers too, so browsers can read it.
+                Err(e) => {
+                    let mut res = e.error_response();
+                    if config
+                        .cors
+                        .insert_headers(res.headers_mut(), origin.as_ref())
+                    {
+                        Err(InternalError::from_response(e, res).into())
+                    } else {
+                        Err(e)
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn preflight(
+    cors: &CorsConfig,
+    origin: &HeaderValue,
+    headers: &HeaderMap,
+) -> Result<HttpResponse, CorsError> {
+    if !cors.allows_origin(origin) {
+        return Err(CorsError::Origin);
+    }
+    let method = headers
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|m| Method::from_bytes(m.as_bytes()).ok());
+    if !method.is_some_and(|m| cors.methods.contains(&m)) {
+        return Err(CorsError::Method);
+    }
+    let requested = headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    for name in requested
+        .split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+    {
+        if !HeaderName::from_str(name).is_ok_and(|h| cors.headers.contains(&h)) {
+            return Err(CorsError::Header(name.to_owned()));
+        }
+    }
+
+    let mut res = HttpResponse::NoContent();
+    res.insert_header((
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        join(cors.methods.iter().map(Method::as_str)),
+    ))
+    .insert_header((
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        join(cors.headers.iter().map(HeaderName::as_str)),
+    ))
+    .insert_header((header::ACCESS_CONTROL_MAX_AGE, cors.max_age.as_secs()))
+    .insert_header((
+        header::VARY,
+        "Access-Control-Request-Method, Access-Control-Request-Headers",
+    ));
+    let mut res = res.finish();
+    cors.insert_origin(res.headers_mut(), origin);
+    Ok(res)
+}
+
+fn join<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+fn from_methods<'de, D>(de: D) -> Result<Vec<Method>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|m| Method::from_str(&m.to_uppercase()).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn from_header_names<'de, D>(de: D) -> Result<Vec<HeaderName>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{error, test as actix_test, web, App};
+
+    use super::*;
+
+    const ORIGIN: HeaderValue = HeaderValue::from_static("https://app.example.com");
+
+    fn explicit() -> CorsConfig {
+        CorsConfig {
+            origins: vec!["https://app.example.com".to_owned()],
+            methods: vec![Method::GET, Method::POST],
+            headers: vec![header::AUTHORIZATION, header::CONTENT_TYPE],
+            credentials: true,
+            ..Default::default()
+        }
+    }
+
+    fn value(headers: &HeaderMap, name: HeaderName) -> &str {
+        headers
+            .get(&name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_else(|| panic!("{name} is missing"))
+    }
+
+    fn request(method: &'static str, headers: &'static str) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        map.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static(method),
+        );
+        map.insert(
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            HeaderValue::from_static(headers),
+        );
+        map
+    }
+
+    #[test]
+    fn preflight_allows_configured_requests() {
+        let res = preflight(
+            &explicit(),
+            &ORIGIN,
+            &request("POST", "Authorization, content-type"),
+        )
+        .unwrap();
+        let headers = res.headers();
+        assert_eq!(
+            value(headers, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            ORIGIN.to_str().unwrap()
+        );
+        assert_eq!(
+            value(headers, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            "true"
+        );
+        assert_eq!(
+            value(headers, header::ACCESS_CONTROL_ALLOW_METHODS),
+            "GET, POST"
+        );
+        assert_eq!(
+            value(headers, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            "authorization, content-type"
+        );
+        let vary = headers
+            .get_all(header::VARY)
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>();
+        assert!(vary.contains(&"Origin"), "{vary:?}");
+
+        // Any origin without credentials doesn't depend on the origin.
+        let res = preflight(&CorsConfig::default(), &ORIGIN, &request("GET", "")).unwrap();
+        assert_eq!(
+            value(res.headers(), header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            "*"
+        );
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[test]
+    fn preflight_rejects_other_requests() {
+        let other = HeaderValue::from_static("https://evil.example.com");
+        assert!(matches!(
+            preflight(&explicit(), &other, &request("GET", "")),
+            Err(CorsError::Origin)
+        ));
+        assert!(matches!(
+            preflight(&explicit(), &ORIGIN, &request("DELETE", "")),
+            Err(CorsError::Method)
+        ));
+        assert!(matches!(
+            preflight(&explicit(), &ORIGIN, &request("POST", "authorization, x-upload-filename")),
+            Err(CorsError::Header(h)) if h == "x-upload-filename"
+        ));
+    }
+
+    #[actix_web::test]
+    async fn adds_headers_to_errors() {
+        crate::config::use_test_config();
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(Cors)
+                .route("/ok", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/401",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(error::ErrorUnauthorized("Invalid authorization"))
+                    }),
+                )
+                .route(
+                    "/413",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(error::ErrorPayloadTooLarge("Too large"))
+                    }),
+                )
+                .route(
+                    "/429",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(error::ErrorTooManyRequests("Too many requests"))
+                    }),
+                ),
+        )
+        .await;
+
+        for (path, status) in [("/ok", 200), ("/401", 401), ("/413", 413), ("/429", 429)] {
+            let req = actix_test::TestRequest::get()
+                .uri(path)
+                .insert_header((header::ORIGIN, ORIGIN))
+                .to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status().as_u16(), status);
+            let headers = res.headers();
+            assert_eq!(
+                value(headers, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                ORIGIN,
+                "{path}"
+            );
+            assert_eq!(
+                value(headers, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+                "true"
+            );
+            assert_eq!(value(headers, header::VARY), "Origin");
+        }
+
+        let req = actix_test::TestRequest::get()
+            .uri("/401")
+            .insert_header((header::ORIGIN, "https://evil.example.com"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 401);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(value(res.headers(), header::VARY), "Origin");
+
+        // Requests without an origin could be cached for any origin.
+        for (path, status) in [("/ok", 200), ("/401", 401)] {
+            let req = actix_test::TestRequest::get().uri(path).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status().as_u16(), status);
+            assert!(!res
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+            assert_eq!(value(res.headers(), header::VARY), "Origin");
+        }
+    }
+
+    #[test]
+    fn varies_unless_any_origin_is_allowed() {
+        let mut headers = HeaderMap::new();
+        assert!(!CorsConfig::default().insert_headers(&mut headers, None));
+        assert!(CorsConfig::default().insert_headers(&mut headers, Some(&ORIGIN)));
+        assert!(!headers.contains_key(header::VARY));
+
+        let other = HeaderValue::from_static("https://evil.example.com");
+        for origin in [None, Some(&ORIGIN), Some(&other)] {
+            let mut headers = HeaderMap::new();
+            assert!(explicit().insert_headers(&mut headers, origin));
+            assert_eq!(value(&headers, header::VARY), "Origin", "{origin:?}");
+
+            // Credentials echo any origin instead of `*`.
+            let cors = CorsConfig {
+                origins: vec!["*".to_owned()],
+                ..explicit()
+            };
+            let mut headers = HeaderMap::new();
+            assert!(cors.insert_headers(&mut headers, origin));
+            assert_eq!(value(&headers, header::VARY), "Origin", "{origin:?}");
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use std::io;
+use std::{
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use actix_files::NamedFile;
-use actix_web::{delete, get, web, HttpResponse, Responder};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
 use base64::{
     engine::{
         general_purpose::{GeneralPurpose, GeneralPurposeConfig},
@@ -9,12 +12,19 @@ use base64::{
     },
     Engine,
 };
-use hmac::{Hmac, KeyInit, Mac};
-use sha2::Sha224;
-
-use crate::config::CONFIG;
+use serde::Serialize;
+use tracing::{info, warn};
 
-type HmacSha224 = Hmac<Sha224>;
+use crate::{
+    audit::{self, Event, Record},
+    auth::{Identity, Scope},
+    config::{self, Config},
+    meta::{self, FileMeta},
+    metrics,
+    ratelimit::ClientIp,
+    secrets,
+    webhooks::{self, WebhookEvent},
+};
 
 const DELETION_KEY_ENGINE: GeneralPurpose = GeneralPurpose::new(
     &base64::alphabet::URL_SAFE,
@@ -23,13 +33,39 @@ const DELETION_KEY_ENGINE: GeneralPurpose = GeneralPurpose::new(
         .with_decode_padding_mode(DecodePaddingMode::Indifferent),
 );
 
-pub fn make_key(link: &str) -> String {
-    let mut mac = HmacSha224::new(&CONFIG.secret);
-    mac.update(link.as_bytes());
-    let bytes = mac.finalize().into_bytes();
-    let mut buf = String::with_capacity(38);
-    DELETION_KEY_ENGINE.encode_string(bytes, &mut buf);
-    buf
+/// The length of a signature with the ID of the secret.
+const SIGNATURE_LEN: usize = 29;
+
+/// Creates a deletion key for a file.
+///
+/// Most keys are only a signature of the filename.
+/// If the key expires or the file's keys were revoked before (the generation is not 0),
+/// the key contains the generation and the expiry, which are included in the signature.
+pub fn make_key(link: &str, generation: u32, expires: Option<SystemTime>) -> String {
+    let expires = expires
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let bytes = if generation == 0 && expires == 0 {
+        secrets::sign(&[link.as_bytes()])
+    } else {
+        let mut bytes = Vec::with_capacity(12 + SIGNATURE_LEN);
+        bytes.extend_from_slice(&generation.to_be_bytes());
+        bytes.extend_from_slice(&expires.to_be_bytes());
+        let signature = secrets::sign(&[link.as_bytes(), b"\0", &bytes]);
+        bytes.extend_from_slice(&signature);
+        bytes
+    };
+    DELETION_KEY_ENGINE.encode(bytes)
+}
+
+/// The link to the deletion page of a file, which expires after `deletion_link_expiry`.
+pub fn link(config: &Config, filename: &str, generation: u32) -> String {
+    let expires = config.deletion_link_expiry.map(|e| SystemTime::now() + e);
+    format!(
+        "{}/d/{filename}/{}",
+        config.domain,
+        make_key(filename, generation, expires)
+    )
 }
 
 #[derive(Debug, thiserror::Error, actix_web_error::Json)]
@@ -37,6 +73,9 @@ pub enum DeletionError {
     #[error("Your key is invalid")]
     #[status(401)]
     InvalidKey,
+    #[error("You can't manage this file")]
+    #[status(403)]
+    Forbidden,
     #[error("This file doesn't exist")]
     #[status(404)]
     NotFound,
@@ -45,14 +84,70 @@ pub enum DeletionError {
     IoError(io::Error),
 }
 
+impl DeletionError {
+    /// The name of the variant for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidKey => "invalid_key",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::IoError(_) => "io",
+        }
+    }
+}
+
 #[delete("/d/{filename}/{key}")]
-pub async fn service(path: web::Path<(String, String)>) -> Result<HttpResponse, DeletionError> {
+pub async fn service(
+    path: web::Path<(String, String)>,
+    ClientIp(ip): ClientIp,
+) -> Result<HttpResponse, DeletionError> {
     let (filename, key) = path.into_inner();
-    if !check_key(&filename, &key) || filename.starts_with('.') {
+    let (size, hash) = audit::file_info(&filename).await;
+    let res = delete_with_key(&filename, &key).await;
+    audit::log(Record {
+        ip,
+        file: Some(filename.clone()),
+        size,
+        hash: hash.clone(),
+        ..Record::new(
+            Event::Deletion,
+            res.as_ref().map_or_else(|e| e.kind(), |_| "ok"),
+        )
+    });
+    res.inspect_err(|e| metrics::deletion_error(e.kind()))?;
+    metrics::deletion("key");
+    webhooks::send(webhooks::Event {
+        size,
+        hash,
+        ..webhooks::Event::new(WebhookEvent::Deletion, filename)
+    })
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn delete_with_key(filename: &str, key: &str) -> Result<(), DeletionError> {
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return Err(DeletionError::InvalidKey);
+    }
+    let generation = match meta::read(filename).await {
+        Ok(meta) => meta.map_or(0, |m| m.deletion_generation),
+        Err(e) => return Err(DeletionError::IoError(e)),
+    };
+    if !check_key(filename, key, generation) {
         return Err(DeletionError::InvalidKey);
     }
-    match tokio::fs::remove_file(&CONFIG.file_dir.join(filename)).await {
-        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+    remove(filename).await
+}
+
+/// Removes an upload and its metadata.
+pub async fn remove(filename: &str) -> Result<(), DeletionError> {
+    match tokio::fs::remove_file(&config::get().file_dir.join(filename)).await {
+        Ok(_) => {
+            if let Err(e) = meta::remove(filename).await {
+                warn!(error = ?e, "Couldn't remove metadata");
+            }
+            Ok(())
+        }
         Err(e) if e.kind() == io::ErrorKind::NotFound => Err(DeletionError::NotFound),
         Err(e) => Err(DeletionError::IoError(e)),
     }
@@ -63,20 +158,273 @@ pub async fn view() -> impl Responder {
     NamedFile::open_async("./static/pages/delete/delete.html").await
 }
 
-fn check_key(link: &str, key: &str) -> bool {
-    if key.len() != 38 {
+#[derive(Serialize)]
+pub struct ReissueResponse {
+    deletion_link: String,
+}
+
+/// Invalidates all deletion keys of a file.
+#[delete("/uploads/{filename}/deletion-key")]
+pub async fn revoke(
+    filename: web::Path<String>,
+    identity: web::ReqData<Identity>,
+) -> Result<HttpResponse, DeletionError> {
+    bump_generation(&filename, &identity).await?;
+    info!(
+        key = identity.name,
+        file = *filename,
+        "Revoked deletion keys"
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invalidates all deletion keys of a file and creates a new one.
+#[post("/uploads/{filename}/deletion-key")]
+pub async fn reissue(
+    filename: web::Path<String>,
+    identity: web::ReqData<Identity>,
+) -> Result<HttpResponse, DeletionError> {
+    let generation = bump_generation(&filename, &identity).await?;
+    info!(
+        key = identity.name,
+        file = *filename,
+        "Reissued deletion key"
+    );
+    Ok(HttpResponse::Ok().json(ReissueResponse {
+        deletion_link: link(&config::get(), &filename, generation),
+    }))
+}
+
+/// Deletes an upload of the key or token, or any upload with the `delete-any` scope.
+#[delete("/uploads/{filename}")]
+pub async fn delete(
+    filename: web::Path<String>,
+    identity: web::ReqData<Identity>,
+    ClientIp(ip): ClientIp,
+) -> Result<HttpResponse, DeletionError> {
+    let filename = filename.into_inner();
+    let (size, hash) = audit::file_info(&filename).await;
+    let res = match managed_meta(&filename, &identity).await {
+        Ok(_) => remove(&filename).await,
+        Err(e) => Err(e),
+    };
+    audit::log(Record {
+        key: Some(identity.name.clone()),
+        ip,
+        file: Some(filename.clone()),
+        size,
+        hash: hash.clone(),
+        ..Record::new(
+            Event::Deletion,
+            res.as_ref().map_or_else(|e| e.kind(), |_| "ok"),
+        )
+    });
+    res.inspect_err(|e| metrics::deletion_error(e.kind()))?;
+    metrics::deletion("api");
+    info!(key = identity.name, file = filename, "Deleted upload");
+    webhooks::send(webhooks::Event {
+        key: Some(identity.name.clone()),
+        size,
+        hash,
+        ..webhooks::Event::new(WebhookEvent::Deletion, filename)
+    })
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn bump_generation(filename: &str, identity: &Identity) -> Result<u32, DeletionError> {
+    let mut meta = managed_meta(filename, identity).await?;
+    meta.deletion_generation += 1;
+    meta::write(filename, &meta)
+        .await
+        .map_err(DeletionError::IoError)?;
+    Ok(meta.deletion_generation)
+}
+
+/// Files can be managed by their uploader and keys with the `delete-any` scope.
+async fn managed_meta(filename: &str, identity: &Identity) -> Result<FileMeta, DeletionError> {
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return Err(DeletionError::NotFound);
+    }
+    match tokio::fs::metadata(config::get().file_dir.join(filename)).await {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(DeletionError::NotFound),
+        Err(e) => return Err(DeletionError::IoError(e)),
+    }
+    let meta: FileMeta = meta::read(filename)
+        .await
+        .map_err(DeletionError::IoError)?
+        .unwrap_or_default();
+    if !identity.scopes.contains(&Scope::DeleteAny)
+        && meta.uploader.as_deref() != Some(identity.name.as_str())
+    {
+        return Err(DeletionError::Forbidden);
+    }
+    Ok(meta)
+}
+
+/// Keys are 39 characters long, keys made before secrets had IDs are 38.
+/// Keys with a generation or expiry are 55 characters long.
+fn check_key(link: &str, key: &str, generation: u32) -> bool {
+    if !matches!(key.len(), 38 | 39 | 55) {
         return false;
     }
 
-    let mut dec = [0; 32];
+    let mut dec = [0; 48];
     let dec_len = match DELETION_KEY_ENGINE.decode_slice(key, &mut dec) {
         Ok(l) => l,
         Err(_) => return false,
     };
+    let dec = &dec[..dec_len];
+    if dec_len <= SIGNATURE_LEN {
+        return generation == 0 && secrets::verify(&[link.as_bytes()], dec);
+    }
+
+    let (fields, signature) = dec.split_at(12);
+    let key_generation = u32::from_be_bytes(fields[..4].try_into().unwrap());
+    let expires = u64::from_be_bytes(fields[4..].try_into().unwrap());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    key_generation == generation
+        && (expires == 0 || expires > now)
+        && secrets::verify(&[link.as_bytes(), b"\0", fields], signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::secrets::tests::sign_with;
+
+    #[test]
+    fn checks_versioned_keys() {
+        config::use_test_config();
+        let key = make_key("file.png", 0, None);
+        assert_eq!(key.len(), 39);
+        assert!(check_key("file.png", &key, 0));
+        assert!(!check_key("other.png", &key, 0));
+    }
+
+    #[test]
+    fn checks_legacy_keys() {
+        config::use_test_config();
+        let key = DELETION_KEY_ENGINE.encode(sign_with(None, 0, &[b"file.png"]));
+        assert_eq!(key.len(), 38);
+        assert!(check_key("file.png", &key, 0));
+        // Only secret 0 made keys without an ID.
+        let key = DELETION_KEY_ENGINE.encode(sign_with(None, 100, &[b"file.png"]));
+        assert!(!check_key("file.png", &key, 0));
+    }
+
+    #[test]
+    fn rejects_keys_of_retired_and_unknown_secrets() {
+        config::use_test_config();
+        let key = DELETION_KEY_ENGINE.encode(sign_with(Some(2), 200, &[b"file.png"]));
+        assert_eq!(key.len(), 39);
+        assert!(!check_key("file.png", &key, 0));
+        let key = DELETION_KEY_ENGINE.encode(sign_with(Some(7), 100, &[b"file.png"]));
+        assert!(!check_key("file.png", &key, 0));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        config::use_test_config();
+        let key = make_key("file.png", 0, None);
+        let expiring = make_key(
+            "file.png",
+            0,
+            Some(SystemTime::now() + Duration::from_secs(60)),
+        );
+        for key in [
+            "",
+            "abc",
+            &key[..38],
+            &key[..20],
+            &expiring[..54],
+            &"!".repeat(39),
+            &"A".repeat(55),
+            &format!("{key}A"),
+        ] {
+            assert!(!check_key("file.png", key, 0), "{key:?}");
+        }
+    }
 
-    let mut mac = HmacSha224::new(&CONFIG.secret);
-    mac.update(link.as_bytes());
-    let bytes = mac.finalize().into_bytes();
+    #[test]
+    fn expiring_keys() {
+        config::use_test_config();
+        let future = SystemTime::now() + Duration::from_secs(60);
+        let key = make_key("file.png", 0, Some(future));
+        assert_eq!(key.len(), 55);
+        assert!(check_key("file.png", &key, 0));
 
-    bytes[..] == dec[..dec_len]
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let key = make_key("file.png", 0, Some(past));
+        assert_eq!(key.len(), 55);
+        assert!(!check_key("file.png", &key, 0));
+    }
+
+    #[test]
+    fn generation_0_keys_keep_the_short_form() {
+        config::use_test_config();
+        let config = config::get();
+        assert_eq!(config.deletion_link_expiry, None);
+        let link = link(&config, "file.png", 0);
+        let key = link.rsplit('/').next().unwrap();
+        assert_eq!(key, make_key("file.png", 0, None));
+        assert_eq!(key.len(), 39);
+        assert_eq!(make_key("file.png", 1, None).len(), 55);
+    }
+
+    #[actix_web::test]
+    async fn bumping_the_generation_revokes_keys() {
+        config::use_test_config();
+        let filename = format!("revoke-{}.png", std::process::id());
+        let file_dir = config::get().file_dir.clone();
+        std::fs::create_dir_all(meta::dir()).unwrap();
+        std::fs::write(file_dir.join(&filename), b"").unwrap();
+        let meta = FileMeta {
+            uploader: Some("sharex".to_owned()),
+            ..Default::default()
+        };
+        meta::write(&filename, &meta).await.unwrap();
+        let old_key = make_key(&filename, 0, None);
+        let identity = Identity {
+            name: "sharex".to_owned(),
+            scopes: vec![Scope::Upload],
+            limits: Default::default(),
+        };
+        let other = Identity {
+            name: "other".to_owned(),
+            ..identity.clone()
+        };
+
+        assert!(matches!(
+            bump_generation(&filename, &other).await,
+            Err(DeletionError::Forbidden)
+        ));
+        assert_eq!(bump_generation(&filename, &identity).await.unwrap(), 1);
+        let generation = meta::read(&filename)
+            .await
+            .unwrap()
+            .unwrap()
+            .deletion_generation;
+        assert_eq!(generation, 1);
+        assert!(!check_key(&filename, &old_key, generation));
+        assert!(check_key(
+            &filename,
+            &make_key(&filename, generation, None),
+            generation
+        ));
+        assert!(matches!(
+            delete_with_key(&filename, &old_key).await,
+            Err(DeletionError::InvalidKey)
+        ));
+        delete_with_key(&filename, &make_key(&filename, 1, None))
+            .await
+            .unwrap();
+        assert!(!file_dir.join(&filename).exists());
+    }
 }
@@ -0,0 +1,691 @@
+//! Determines the type of uploaded files.
+//!
+//! The type is decided based on the content of the file and the claims of the client.
+//! The content is inspected in the following order - the first match wins:
+//!
+//! 1. Magic bytes (through [`infer`]).
+//! 2. Text heuristics - if the content is valid text, it's treated as plain text.
+//!    The encoding is taken from a byte order mark if there is one.
+//!    Otherwise UTF-8 is tried first, and then the encoding guessed by [`chardetng`].
+//!    Text must not contain control characters other than whitespace.
+//! 3. Otherwise, the content is unknown (`bin`).
+//!
+//! The client can claim a type through the extension of the uploaded filename
+//! or through the `Content-Type` of the upload. The extension takes precedence over the
+//! `Content-Type`. Generic types like `application/octet-stream` aren't considered a claim.
+//!
+//! If the claim is compatible with the content, the claimed extension is used,
+//! as it's usually more specific (e.g. `rs` instead of `txt`).
+//! If the content is unknown, the claim is always used.
+//! Otherwise, the [`MismatchPolicy`] decides what happens.
+
+use std::{borrow::Cow, fmt, path::Path};
+
+use bytes::{Bytes, BytesMut};
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_8};
+use futures::{Stream, StreamExt};
+use infer::MatcherType;
+use serde::Deserialize;
+
+/// Number of bytes inspected to determine the type of a file.
+pub const SNIFF_LEN: usize = 4096;
+
+/// A hint on which view should be used for a file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TypeHint {
+    None,
+    Audio,
+    Text,
+    Pdf,
+}
+
+impl TypeHint {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Audio => "audio",
+            Self::Text => "text",
+            Self::Pdf => "pdf",
+        }
+    }
+}
+
+/// What to do if the type claimed by the client doesn't match the content.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MismatchPolicy {
+    /// Keep the claimed extension.
+    Trust,
+    /// Use the extension matching the content.
+    #[default]
+    Override,
+    /// Reject the upload.
+    Reject,
+}
+
+impl MismatchPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trust => "trust",
+            Self::Override => "override",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Detected {
+    pub extension: Cow<'static, str>,
+    pub hint: TypeHint,
+    /// The encoding of text files (e.g. `UTF-8` or `windows-1252`).
+    pub charset: Option<&'static str>,
+    /// The claim that didn't match the content if the policy allowed the upload anyway.
+    pub mismatch: Option<Mismatch>,
+}
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+#[error("The content ({detected}) doesn't match the specified type ({claimed})")]
+pub struct Mismatch {
+    pub claimed: String,
+    pub detected: &'static str,
+}
+
+/// What the content of a file looks like.
+enum Content {
+    Magic(infer::Type),
+    Text(&'static Encoding),
+    Unknown,
+}
+
+/// What the client says the file is.
+enum Claim<'a> {
+    Extension(&'a str),
+    Mime(&'a mime::Mime),
+}
+
+impl Content {
+    fn inspect(buf: &[u8]) -> Self {
+        if let Some(ty) = infer::get(buf) {
+            Self::Magic(ty)
+        } else if let Some(encoding) = text_encoding(buf, buf.len() < SNIFF_LEN) {
+            Self::Text(encoding)
+        } else {
+            Self::Unknown
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Magic(ty) => ty.extension(),
+            Self::Text(_) => "txt",
+            Self::Unknown => "bin",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Magic(ty) => ty.mime_type(),
+            Self::Text(_) => "text/plain",
+            Self::Unknown => "application/octet-stream",
+        }
+    }
+
+    fn is_textual(&self) -> bool {
+        match self {
+            Self::Magic(ty) => ty.matcher_type() == MatcherType::Text,
+            Self::Text(_) => true,
+            Self::Unknown => false,
+        }
+    }
+
+    fn accepts(&self, claim: &Claim) -> bool {
+        match self {
+            Self::Unknown => true,
+            Self::Text(_) => claim.is_textual(),
+            Self::Magic(ty) if ty.matcher_type() == MatcherType::Text => claim.is_textual(),
+            Self::Magic(ty) => match claim {
+                Claim::Extension(ext) if ext.eq_ignore_ascii_case(ty.extension()) => true,
+                _ => claim
+                    .guesses()
+                    .is_some_and(|g| g.iter().any(|m| same_mime(m, ty.mime_type()))),
+            },
+        }
+    }
+}
+
+impl<'a> Claim<'a> {
+    fn new(content_type: Option<&'a mime::Mime>, filename: Option<&'a Path>) -> Option<Self> {
+        if let Some(ext) = filename
+            .and_then(|p| p.extension())
+            .and_then(|s| s.to_str())
+            .filter(|s| !s.is_empty())
+        {
+            return Some(Self::Extension(ext));
+        }
+        content_type.filter(|m| is_specific(m)).map(Self::Mime)
+    }
+
+    /// The MIME types this claim could refer to, `None` if it's unknown.
+    fn guesses(&self) -> Option<Vec<mime::Mime>> {
+        match self {
+            Self::Extension(ext) => {
+                let guess = mime_guess::from_ext(ext);
+                (!guess.is_empty()).then(|| guess.iter().collect())
+            }
+            Self::Mime(mime) => Some(vec![(*mime).clone()]),
+        }
+    }
+
+    /// Text based formats can't be told apart reliably, so any of them is accepted.
+    /// Unknown extensions are most likely text as well.
+    fn is_textual(&self) -> bool {
+        self.guesses().is_none_or(|g| g.iter().any(is_textual))
+    }
+
+    fn extension(&self) -> Option<Cow<'static, str>> {
+        match self {
+            Self::Extension(ext) => Some(Cow::Owned(ext.to_string())),
+            Self::Mime(mime) => extension_from_mime(mime).map(Cow::Borrowed),
+        }
+    }
+}
+
+impl fmt::Display for Claim<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Extension(ext) => write!(f, ".{ext}"),
+            Self::Mime(mime) => write!(f, "{}", mime.essence_str()),
+        }
+    }
+}
+
+/// Determines the extension and view of a file from its first [`SNIFF_LEN`] bytes.
+pub fn detect(
+    buf: &[u8],
+    content_type: Option<&mime::Mime>,
+    filename: Option<&Path>,
+    policy: MismatchPolicy,
+) -> Result<Detected, Mismatch> {
+    let content = Content::inspect(buf);
+    let claim = Claim::new(content_type, filename);
+
+    let mut mismatch = None;
+    let extension = match claim {
+        None => Cow::Borrowed(content.extension()),
+        Some(claim) if content.accepts(&claim) => claim
+            .extension()
+            .unwrap_or(Cow::Borrowed(content.extension())),
+        Some(claim) => {
+            let found = Mismatch {
+                claimed: claim.to_string(),
+                detected: content.mime_type(),
+            };
+            match policy {
+                MismatchPolicy::Trust => {
+                    mismatch = Some(found);
+                    claim
+                        .extension()
+                        .unwrap_or(Cow::Borrowed(content.extension()))
+                }
+                MismatchPolicy::Override => {
+                    mismatch = Some(found);
+                    Cow::Borrowed(content.extension())
+                }
+                MismatchPolicy::Reject => return Err(found),
+            }
+        }
+    };
+
+    let mime = mime_guess::from_ext(&extension).first();
+    let hint = match &mime {
+        Some(mime) => type_hint(mime),
+        None if content.is_textual() => TypeHint::Text,
+        None => TypeHint::None,
+    };
+    // If the client insisted on a binary format, the charset doesn't make sense.
+    let charset = match content {
+        Content::Text(encoding) if mime.as_ref().is_none_or(is_textual) => Some(encoding.name()),
+        _ => None,
+    };
+
+    Ok(Detected {
+        extension,
+        hint,
+        charset,
+        mismatch,
+    })
+}
+
+/// Reads the first [`SNIFF_LEN`] bytes (or less if the stream ends) of a stream.
+pub async fn sniff<S, E>(stream: &mut S) -> Result<Bytes, E>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let first = match stream.next().await {
+        Some(item) => item?,
+        None => return Ok(Bytes::new()),
+    };
+    if first.len() >= SNIFF_LEN {
+        // this should be more common
+        return Ok(first);
+    }
+
+    let mut bytes = BytesMut::from(first.as_ref());
+    while bytes.len() < SNIFF_LEN {
+        match stream.next().await {
+            Some(item) => bytes.extend_from_slice(&item?),
+            None => break,
+        }
+    }
+    Ok(bytes.freeze())
+}
+
+/// Determines the encoding of `buf` if it's text.
+///
+/// `last` specifies if `buf` contains the whole file.
+/// If it doesn't, a character cut at the end of `buf` is allowed.
+fn text_encoding(buf: &[u8], last: bool) -> Option<&'static Encoding> {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(buf) {
+        return is_clean_text(encoding, &buf[bom_len..], last).then_some(encoding);
+    }
+    if is_clean_text(UTF_8, buf, last) {
+        return Some(UTF_8);
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(buf, last);
+    let encoding = detector.guess(None, false);
+    is_clean_text(encoding, buf, last).then_some(encoding)
+}
+
+/// Checks that `buf` can be decoded without errors and doesn't contain binary control characters.
+fn is_clean_text(encoding: &'static Encoding, buf: &[u8], last: bool) -> bool {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let Some(capacity) = decoder.max_utf8_buffer_length_without_replacement(buf.len()) else {
+        return false;
+    };
+    let mut text = String::with_capacity(capacity);
+    let (res, _) = decoder.decode_to_string_without_replacement(buf, &mut text, last);
+
+    res == DecoderResult::InputEmpty
+        && !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+}
+
+/// Checks if an extension matches an extension (`png`) or a MIME type (`image/png`, `image/*`).
+pub fn matches_type(pattern: &str, extension: &str) -> bool {
+    let Some((type_, subtype)) = pattern.split_once('/') else {
+        return pattern.eq_ignore_ascii_case(extension);
+    };
+    mime_guess::from_ext(extension)
+        .iter()
+        .any(|mime| match subtype {
+            "*" => mime.type_().as_str().eq_ignore_ascii_case(type_),
+            _ => mime.essence_str().eq_ignore_ascii_case(pattern),
+        })
+}
+
+/// Generic types don't tell us anything about the content.
+fn is_specific(mime: &mime::Mime) -> bool {
+    mime.type_() != mime::STAR
+        && mime.subtype() != mime::STAR
+        && mime.type_() != mime::MULTIPART
+        && mime.essence_str() != mime::APPLICATION_OCTET_STREAM.essence_str()
+}
+
+/// Whether a MIME type describes a text based format.
+fn is_textual(mime: &mime::Mime) -> bool {
+    if mime.type_() == mime::TEXT
+        || mime.suffix() == Some(mime::XML)
+        || mime.suffix() == Some(mime::JSON)
+    {
+        return true;
+    }
+    mime.type_() == mime::APPLICATION
+        && matches!(
+            mime.subtype().as_str(),
+            "json"
+                | "xml"
+                | "javascript"
+                | "ecmascript"
+                | "toml"
+                | "yaml"
+                | "x-yaml"
+                | "x-sh"
+                | "x-csh"
+                | "x-tex"
+                | "x-latex"
+                | "x-perl"
+                | "x-python"
+                | "sql"
+        )
+}
+
+/// Compares two MIME types while ignoring the `x-` prefix of the subtype,
+/// since [`infer`] and [`mime_guess`] don't always agree on it (e.g. `audio/x-flac`).
+fn same_mime(a: &mime::Mime, b: &str) -> bool {
+    let Some((b_type, b_subtype)) = b.split_once('/') else {
+        return false;
+    };
+    let strip = |s: &str| s.strip_prefix("x-").unwrap_or(s).to_owned();
+    a.type_() == b_type && strip(a.subtype().as_str()) == strip(b_subtype)
+}
+
+fn extension_from_mime(mime: &mime::Mime) -> Option<&'static str> {
+    // Extensions are sorted alphabetically, so we need to prefer some common ones.
+    const PREFERRED: &[(&str, &str)] = &[
+        ("text/plain", "txt"),
+        ("text/javascript", "js"),
+        ("application/javascript", "js"),
+        ("text/markdown", "md"),
+        ("image/jpeg", "jpg"),
+        ("image/svg+xml", "svg"),
+        ("audio/mpeg", "mp3"),
+        ("video/quicktime", "mov"),
+    ];
+    if let Some((_, ext)) = PREFERRED.iter().find(|(m, _)| *m == mime.essence_str()) {
+        return Some(ext);
+    }
+    let extensions = mime_guess::get_mime_extensions(mime)?;
+    extensions
+        .iter()
+        .find(|ext| **ext == mime.subtype().as_str())
+        .or_else(|| extensions.first())
+        .copied()
+}
+
+/// The view of a stored file, based on its extension.
+pub fn hint_for_extension(extension: &str) -> TypeHint {
+    mime_guess::from_ext(extension)
+        .first()
+        .map_or(TypeHint::None, |mime| type_hint(&mime))
+}
+
+fn type_hint(mime: &mime::Mime) -> TypeHint {
+    if mime.type_() == mime::AUDIO {
+        TypeHint::Audio
+    } else if *mime == mime::APPLICATION_PDF {
+        TypeHint::Pdf
+    } else if mime.type_() != mime::IMAGE && is_textual(mime) {
+        TypeHint::Text
+    } else {
+        TypeHint::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! corpus {
+        ($name:literal) => {
+            include_bytes!(concat!("../tests/corpus/", $name)).as_slice()
+        };
+    }
+
+    fn check(
+        buf: &[u8],
+        content_type: Option<&str>,
+        filename: Option<&str>,
+        policy: MismatchPolicy,
+    ) -> Result<(String, TypeHint), Mismatch> {
+        let mime = content_type.map(|c| c.parse::<mime::Mime>().unwrap());
+        detect(buf, mime.as_ref(), filename.map(Path::new), policy)
+            .map(|d| (d.extension.into_owned(), d.hint))
+    }
+
+    fn trust(buf: &[u8], content_type: Option<&str>, filename: Option<&str>) -> (String, TypeHint) {
+        check(buf, content_type, filename, MismatchPolicy::Trust).unwrap()
+    }
+
+    #[test]
+    fn magic_without_claim() {
+        assert_eq!(
+            trust(corpus!("image.png"), None, None),
+            ("png".into(), TypeHint::None)
+        );
+        assert_eq!(
+            trust(corpus!("audio.mp3"), None, None),
+            ("mp3".into(), TypeHint::Audio)
+        );
+        assert_eq!(
+            trust(corpus!("document.pdf"), None, None),
+            ("pdf".into(), TypeHint::Pdf)
+        );
+        assert_eq!(
+            trust(corpus!("page.html"), None, None),
+            ("html".into(), TypeHint::Text)
+        );
+    }
+
+    #[test]
+    fn magic_with_matching_claim() {
+        assert_eq!(
+            trust(corpus!("image.png"), None, Some("screenshot.PNG")),
+            ("PNG".into(), TypeHint::None)
+        );
+        assert_eq!(
+            trust(corpus!("image.png"), Some("image/png"), None),
+            ("png".into(), TypeHint::None)
+        );
+        assert_eq!(
+            trust(corpus!("audio.mp3"), Some("audio/mpeg"), Some("song.mp3")),
+            ("mp3".into(), TypeHint::Audio)
+        );
+    }
+
+    #[test]
+    fn generic_content_type_is_ignored() {
+        for ct in ["application/octet-stream", "*/*", "image/*"] {
+            assert_eq!(
+                trust(corpus!("image.png"), Some(ct), None),
+                ("png".into(), TypeHint::None)
+            );
+            assert_eq!(
+                check(corpus!("image.png"), Some(ct), None, MismatchPolicy::Reject).unwrap(),
+                ("png".into(), TypeHint::None)
+            );
+        }
+    }
+
+    #[test]
+    fn extension_takes_precedence_over_content_type() {
+        assert_eq!(
+            trust(corpus!("text.txt"), Some("text/plain"), Some("main.rs")),
+            ("rs".into(), TypeHint::Text)
+        );
+    }
+
+    #[test]
+    fn magic_mismatch() {
+        let png = corpus!("image.png");
+        assert_eq!(
+            check(png, None, Some("a.mp3"), MismatchPolicy::Trust).unwrap(),
+            ("mp3".into(), TypeHint::Audio)
+        );
+        assert_eq!(
+            check(png, None, Some("a.mp3"), MismatchPolicy::Override).unwrap(),
+            ("png".into(), TypeHint::None)
+        );
+        let err = check(png, None, Some("a.mp3"), MismatchPolicy::Reject).unwrap_err();
+        assert_eq!(err.claimed, ".mp3");
+        assert_eq!(err.detected, "image/png");
+
+        let err = check(png, Some("audio/mpeg"), None, MismatchPolicy::Reject).unwrap_err();
+        assert_eq!(err.claimed, "audio/mpeg");
+        assert_eq!(
+            check(png, Some("audio/mpeg"), None, MismatchPolicy::Trust).unwrap(),
+            ("mp3".into(), TypeHint::Audio)
+        );
+    }
+
+    #[test]
+    fn text_without_claim() {
+        assert_eq!(
+            trust(corpus!("text.txt"), None, None),
+            ("txt".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            trust(corpus!("data.json"), None, None),
+            ("txt".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            trust(corpus!("image.svg"), None, None),
+            ("txt".into(), TypeHint::Text)
+        );
+    }
+
+    #[test]
+    fn text_with_textual_claim() {
+        let policy = MismatchPolicy::Reject;
+        assert_eq!(
+            check(corpus!("data.json"), None, Some("data.json"), policy).unwrap(),
+            ("json".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            check(corpus!("text.txt"), Some("text/markdown"), None, policy).unwrap(),
+            ("md".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            check(corpus!("text.txt"), Some("text/plain"), None, policy).unwrap(),
+            ("txt".into(), TypeHint::Text)
+        );
+        // unknown extensions are assumed to be text
+        assert_eq!(
+            check(corpus!("text.txt"), None, Some("notes.uploader"), policy).unwrap(),
+            ("uploader".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            check(corpus!("image.svg"), None, Some("image.svg"), policy).unwrap(),
+            ("svg".into(), TypeHint::None)
+        );
+        // detected as XML by the magic bytes
+        assert_eq!(
+            check(corpus!("prolog.svg"), None, Some("image.svg"), policy).unwrap(),
+            ("svg".into(), TypeHint::None)
+        );
+    }
+
+    #[test]
+    fn reports_allowed_mismatches() {
+        let png = corpus!("image.png");
+        let mismatch = |filename: &str, policy| {
+            detect(png, None, Some(Path::new(filename)), policy)
+                .unwrap()
+                .mismatch
+        };
+        for policy in [MismatchPolicy::Trust, MismatchPolicy::Override] {
+            assert_eq!(
+                mismatch("a.mp3", policy),
+                Some(Mismatch {
+                    claimed: ".mp3".into(),
+                    detected: "image/png",
+                })
+            );
+            assert_eq!(mismatch("a.png", policy), None);
+        }
+        assert_eq!(mismatch("a", MismatchPolicy::Trust), None);
+    }
+
+    #[test]
+    fn text_mismatch() {
+        let text = corpus!("text.txt");
+        assert_eq!(
+            check(text, None, Some("a.png"), MismatchPolicy::Trust).unwrap(),
+            ("png".into(), TypeHint::None)
+        );
+        assert_eq!(
+            check(text, None, Some("a.png"), MismatchPolicy::Override).unwrap(),
+            ("txt".into(), TypeHint::Text)
+        );
+        let err = check(text, None, Some("a.png"), MismatchPolicy::Reject).unwrap_err();
+        assert_eq!(err.detected, "text/plain");
+    }
+
+    #[test]
+    fn unknown_content() {
+        let bin = corpus!("random.bin");
+        assert_eq!(trust(bin, None, None), ("bin".into(), TypeHint::None));
+        assert_eq!(
+            trust(bin, Some("application/octet-stream"), None),
+            ("bin".into(), TypeHint::None)
+        );
+        // the claim is always used
+        assert_eq!(
+            check(bin, None, Some("save.dat"), MismatchPolicy::Reject).unwrap(),
+            ("dat".into(), TypeHint::None)
+        );
+        assert_eq!(
+            check(bin, Some("audio/ogg"), None, MismatchPolicy::Reject).unwrap(),
+            ("ogg".into(), TypeHint::Audio)
+        );
+    }
+
+    fn charset(buf: &[u8], filename: Option<&str>) -> Option<&'static str> {
+        detect(buf, None, filename.map(Path::new), MismatchPolicy::Trust)
+            .unwrap()
+            .charset
+    }
+
+    #[test]
+    fn text_encodings() {
+        assert_eq!(charset(corpus!("text.txt"), None), Some("UTF-8"));
+        assert_eq!(charset(corpus!("utf16le.log"), None), Some("UTF-16LE"));
+        assert_eq!(charset(corpus!("latin1.txt"), None), Some("windows-1252"));
+        assert_eq!(
+            trust(corpus!("utf16le.log"), None, Some("server.log")),
+            ("log".into(), TypeHint::Text)
+        );
+        assert_eq!(
+            trust(corpus!("latin1.txt"), None, None),
+            ("txt".into(), TypeHint::Text)
+        );
+        assert_eq!(charset(corpus!("random.bin"), None), None);
+        assert_eq!(charset(corpus!("image.png"), None), None);
+        // the client insisted on a binary format
+        assert_eq!(charset(corpus!("text.txt"), Some("a.png")), None);
+    }
+
+    #[test]
+    fn utf8_cut_at_buffer_edge() {
+        let mut buf = b"a".to_vec();
+        buf.extend_from_slice("ü".repeat(SNIFF_LEN / 2).as_bytes());
+        buf.truncate(SNIFF_LEN);
+        assert_eq!(buf.last(), Some(&0xc3)); // first byte of 'ü'
+        assert_eq!(charset(&buf, None), Some("UTF-8"));
+        // ...but not if the file ends there
+        buf.truncate(SNIFF_LEN - 2);
+        assert_eq!(buf.last(), Some(&0xc3));
+        assert_ne!(charset(&buf, None), Some("UTF-8"));
+    }
+
+    #[test]
+    fn binary_controls_are_not_text() {
+        assert_eq!(charset(b"abc\0def", None), None);
+        assert_eq!(charset(b"abc\x01\x02\x03", None), None);
+        assert_eq!(charset(b"tab\tand\r\nnewline\x1b[0m", None), Some("UTF-8"));
+    }
+
+    #[actix_web::test]
+    async fn sniff_joins_chunks() {
+        let chunks = corpus!("document.pdf")
+            .chunks(7)
+            .map(|c| Ok::<_, std::convert::Infallible>(Bytes::copy_from_slice(c)))
+            .collect::<Vec<_>>();
+        let mut stream = futures::stream::iter(chunks);
+        let buf = sniff(&mut stream).await.unwrap();
+        assert_eq!(buf.as_ref(), corpus!("document.pdf"));
+    }
+
+    #[test]
+    fn type_patterns() {
+        assert!(matches_type("png", "PNG"));
+        assert!(matches_type("image/*", "png"));
+        assert!(matches_type("image/svg+xml", "svg"));
+        assert!(matches_type("text/plain", "txt"));
+        assert!(!matches_type("image/*", "txt"));
+        assert!(!matches_type("image/png", "unknownext"));
+    }
+}
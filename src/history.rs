@@ -0,0 +1,164 @@
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::{self, AdminError},
+    auth::{AuthError, Identity, Scope},
+    config, deletion, detect, meta, upload,
+};
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Starts at 1.
+    #[serde(default = "admin::default_page")]
+    page: usize,
+    #[serde(default = "admin::default_per_page")]
+    per_page: usize,
+    /// Another key or token, this needs the `read-private` scope.
+    uploader: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    name: String,
+    link: String,
+    /// Regenerated, so it's valid even if the original link was lost.
+    /// Only included for the uploads of the key or token used for the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletion_link: Option<String>,
+    size: u64,
+    /// Unix timestamp in seconds.
+    uploaded: u64,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    total: usize,
+    page: usize,
+    per_page: usize,
+    /// Newest first.
+    uploads: Vec<HistoryEntry>,
+}
+
+/// The uploads of the key or token used for the request, or of the `uploader`.
+#[get("/uploads")]
+pub async fn history(
+    query: web::Query<HistoryQuery>,
+    identity: web::ReqData<Identity>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let uploader = query.uploader.as_ref().unwrap_or(&identity.name);
+    let own = *uploader == identity.name;
+    if !own && !identity.scopes.contains(&Scope::ReadPrivate) {
+        return Err(AuthError::MissingScope(Scope::ReadPrivate).into());
+    }
+    let config = config::get();
+    let per_page = query.per_page.clamp(1, admin::MAX_PER_PAGE);
+    let names = meta::uploads_of(uploader).await.map_err(AdminError::Io)?;
+    let mut uploads = admin::read_uploads().await.map_err(AdminError::Io)?;
+    uploads.retain(|entry| names.contains(&entry.name));
+    uploads.sort_unstable_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    // Only the metadata of the page is read.
+    let total = uploads.len();
+    let mut page = Vec::with_capacity(per_page);
+    for entry in admin::paginate(uploads, query.page, per_page) {
+        let meta = admin::read_meta(&entry.name).await;
+        let hint = detect::hint_for_extension(admin::extension(&entry.name));
+        page.push(HistoryEntry {
+            link: upload::link(&config, &entry.name, hint),
+            // Deletion links would allow deleting the uploads of others.
+            deletion_link: own
+                .then(|| deletion::link(&config, &entry.name, meta.deletion_generation)),
+            size: entry.size,
+            uploaded: admin::timestamp(entry.modified),
+            name: entry.name,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(HistoryResponse {
+        total,
+        page: query.page,
+        per_page,
+        uploads: page,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as actix_test, App, HttpMessage};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::meta::FileMeta;
+
+    async fn store(filename: &str, uploader: &str) {
+        std::fs::write(config::get().file_dir.join(filename), b"").unwrap();
+        let meta = FileMeta {
+            uploader: Some(uploader.to_owned()),
+            ..Default::default()
+        };
+        meta::write(filename, &meta).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn lists_uploads_of_the_requester() {
+        config::use_test_config();
+        std::fs::create_dir_all(meta::dir()).unwrap();
+        let pid = std::process::id();
+        let (alice, bob) = (format!("alice-{pid}"), format!("bob-{pid}"));
+        store(&format!("history-alice-{pid}.png"), &alice).await;
+        store(&format!("history-bob-{pid}.png"), &bob).await;
+
+        let app = actix_test::init_service(App::new().service(history)).await;
+        let list = |name: &str, scopes: Vec<Scope>, query: &str| {
+            let req = actix_test::TestRequest::get()
+                .uri(&format!("/uploads{query}"))
+                .to_request();
+            req.extensions_mut().insert(Identity {
+                name: name.to_owned(),
+                scopes,
+                limits: Default::default(),
+            });
+            actix_test::call_service(&app, req)
+        };
+
+        let res = list(&alice, vec![Scope::Upload], "").await;
+        assert_eq!(res.status(), 200);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["total"], 1);
+        let upload = &body["uploads"][0];
+        assert_eq!(upload["name"], format!("history-alice-{pid}.png"));
+        assert!(upload["deletion_link"].is_string(), "{upload}");
+
+        // The uploads of others need `read-private` and don't have deletion links.
+        let query = format!("?uploader={bob}");
+        let res = list(&alice, vec![Scope::Upload], &query).await;
+        assert_eq!(res.status(), 403);
+        let res = list(&alice, vec![Scope::ReadPrivate], &query).await;
+        assert_eq!(res.status(), 200);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["total"], 1);
+        let upload = &body["uploads"][0];
+        assert_eq!(upload["name"], format!("history-bob-{pid}.png"));
+        assert!(upload.get("deletion_link").is_none(), "{upload}");
+
+        // Asking for your own uploads doesn't need the scope.
+        let res = list(&bob, vec![], &query).await;
+        assert_eq!(res.status(), 200);
+        let res = list(&format!("carol-{pid}"), vec![], "").await;
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["total"], 0);
+
+        for filename in [
+            format!("history-alice-{pid}.png"),
+            format!("history-bob-{pid}.png"),
+        ] {
+            std::fs::remove_file(config::get().file_dir.join(&filename)).unwrap();
+            meta::remove(&filename).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use base64::Engine;
+use rand::Rng;
+use serde_json::json;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Table};
+
+use crate::{auth, config, secrets};
+
+const DEFAULT_BIND: &str = "127.0.0.1:4833";
+const DEFAULT_FILE_DIR: &str = "uploads";
+/// The number of random bytes in the generated key.
+const KEY_LEN: usize = 32;
+
+pub struct InitOptions {
+    pub domain: Option<String>,
+    pub bind: Option<String>,
+    pub file_dir: Option<PathBuf>,
+    /// Use the defaults instead of asking.
+    pub yes: bool,
+    pub force: bool,
+}
+
+/// Creates a config with a new secret and key and prints configs for ShareX and Chatterino.
+pub fn init(options: InitOptions) -> io::Result<()> {
+    let path = config::path();
+    if path.exists() && !options.force {
+        return Err(io::Error::other(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        )));
+    }
+    let interactive = !options.yes && io::stdin().is_terminal();
+
+    let bind = match options.bind {
+        Some(bind) => bind,
+        None => ask(
+            interactive,
+            "Address to listen on",
+            DEFAULT_BIND,
+            config::check_bind,
+        )?,
+    };
+    if let Some(problem) = config::check_bind(&bind) {
+        return Err(io::Error::other(problem));
+    }
+    let domain = match options.domain {
+        Some(domain) => domain,
+        None => ask(
+            interactive,
+            "Public URL of the instance",
+            &format!("http://{bind}"),
+            |d| config::check_origin("domain", d),
+        )?,
+    };
+    if let Some(problem) = config::check_origin("domain", &domain) {
+        return Err(io::Error::other(problem));
+    }
+    let file_dir = match options.file_dir {
+        Some(dir) => dir,
+        None => ask(
+            interactive,
+            "Directory for uploads",
+            DEFAULT_FILE_DIR,
+            |_| None,
+        )?
+        .into(),
+    };
+
+    let mut key = [0; KEY_LEN];
+    rand::rng().fill_bytes(&mut key);
+    let authorization = format!(
+        "Bearer {}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(key)
+    );
+
+    let mut doc = DocumentMut::new();
+    doc["bind"] = value(&bind);
+    doc["file_dir"] = value(file_dir.to_string_lossy().as_ref());
+    doc["domain"] = value(&domain);
+    doc["secret"] = value(secrets::generate());
+    let mut key = Table::new();
+    key["name"] = value("default");
+    key["hash"] = value(
+        base64::engine::general_purpose::STANDARD.encode(auth::hash_key(authorization.as_bytes())),
+    );
+    let mut keys = ArrayOfTables::new();
+    keys.push(key);
+    doc["keys"] = keys.into();
+    doc.decor_mut()
+        .set_prefix("# Generated by `uploader init`, see the README for all options.\n");
+
+    create_config(path, options.force)?.write_all(doc.to_string().as_bytes())?;
+    std::fs::create_dir_all(&file_dir)?;
+    eprintln!("Created {} and {}", path.display(), file_dir.display());
+
+    print_uploaders(&domain, &authorization)
+}
+
+/// Creates the config file, which only the owner can read on Unix since it contains the secret.
+fn create_config(path: &Path, force: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    match force {
+        true => options.create(true).truncate(true),
+        false => options.create_new(true),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // The mode only applies to new files, an overwritten one keeps its permissions.
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+/// Asks for a value on stdin until it's valid.
+fn ask(
+    interactive: bool,
+    question: &str,
+    default: &str,
+    check: impl Fn(&str) -> Option<String>,
+) -> io::Result<String> {
+    if !interactive {
+        return Ok(default.to_owned());
+    }
+    loop {
+        eprint!("{question} [{default}]: ");
+        io::stderr().flush()?;
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        let answer = match line.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match check(answer) {
+            Some(problem) => eprintln!("{problem}"),
+            None => return Ok(answer.to_owned()),
+        }
+    }
+}
+
+fn print_uploaders(domain: &str, authorization: &str) -> io::Result<()> {
+    let upload_url = format!("{domain}/upload");
+    let sharex = json!({
+        "Version": "14.1.0",
+        "Name": "Uploader",
+        "DestinationType": "ImageUploader, TextUploader, FileUploader",
+        "RequestMethod": "POST",
+        "RequestURL": upload_url,
+        "Headers": {
+            "Authorization": authorization,
+        },
+        "Body": "MultipartFormData",
+        "FileFormName": "file",
+        "URL": "{json:link}",
+        "DeletionURL": "{json:deletion_link}",
+    });
+    let chatterino = json!({
+        "external": {
+            "imageUploader": {
+                "enabled": true,
+                "url": upload_url,
+                "formField": "file",
+                "headers": format!("Authorization: {authorization}"),
+                "link": "{link}",
+                "deletionLink": "{deletion_link}",
+            },
+        },
+    });
+
+    println!("Authorization: {authorization}");
+    println!("This is only shown once, the config only contains its hash.");
+    println!();
+    println!("ShareX (save as uploader.sxcu):");
+    println!("{}", serde_json::to_string_pretty(&sharex)?);
+    println!();
+    println!("Chatterino (settings.json):");
+    println!("{}", serde_json::to_string_pretty(&chatterino)?);
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::{io, net::TcpListener, path::PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+/// An entry of `bind`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawListener")]
+pub enum Listener {
+    /// A TCP address like `127.0.0.1:4833`.
+    Tcp(String),
+    /// A Unix socket, an existing socket at the path is replaced.
+    Unix {
+        path: PathBuf,
+        /// The permissions of the socket (e.g. `0o660`).
+        mode: Option<u32>,
+    },
+    /// The sockets passed by systemd with `LISTEN_FDS`.
+    Systemd,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawListener {
+    Address(String),
+    Unix { unix: PathBuf, mode: Option<u32> },
+}
+
+impl TryFrom<RawListener> for Listener {
+    type Error = String;
+
+    fn try_from(raw: RawListener) -> Result<Self, Self::Error> {
+        Ok(match raw {
+            RawListener::Address(address) if address == "systemd" => Self::Systemd,
+            RawListener::Address(address) => Self::Tcp(address),
+            RawListener::Unix { unix, mode } => {
+                if let Some(mode) = mode.filter(|m| *m > 0o777) {
+                    return Err(format!("Invalid mode {mode:#o} for {}", unix.display()));
+                }
+                Self::Unix { path: unix, mode }
+            }
+        })
+    }
+}
+
+impl Listener {
+    /// The port of a TCP address.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Tcp(address) => crate::config::bind_port(address),
+            _ => None,
+        }
+    }
+}
+
+/// Accepts a single listener or a list of them.
+pub fn one_or_many<'de, D>(de: D) -> Result<Vec<Listener>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Listener),
+        Many(Vec<Listener>),
+    }
+    Ok(match OneOrMany::deserialize(de)? {
+        OneOrMany::One(listener) => vec![listener],
+        OneOrMany::Many(listeners) => listeners,
+    })
+}
+
+/// `LISTEN_PID` and `LISTEN_FDS`, which describe the sockets passed by systemd.
+#[derive(Clone, Debug, Default)]
+pub struct ListenEnv {
+    pid: Option<String>,
+    fds: Option<String>,
+}
+
+impl ListenEnv {
+    /// Reads and removes the variables, so child processes don't try to take the sockets.
+    ///
+    /// Changing the environment isn't thread-safe, so this has to run before the runtime starts.
+    pub fn take() -> Self {
+        let env = Self {
+            pid: std::env::var("LISTEN_PID").ok(),
+            fds: std::env::var("LISTEN_FDS").ok(),
+        };
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+        env
+    }
+
+    /// The number of sockets passed to this process.
+    #[cfg(unix)]
+    fn count(&self, pid: u32) -> io::Result<i32> {
+        match (&self.pid, &self.fds) {
+            (Some(p), Some(fds)) if *p == pid.to_string() => fds
+                .parse()
+                .map_err(|_| io::Error::other("LISTEN_FDS isn't a number")),
+            _ => Err(io::Error::other("systemd didn't pass any sockets")),
+        }
+    }
+}
+
+/// A bound socket for the server.
+pub enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Binds all listeners.
+pub fn open(listeners: &[Listener], env: &ListenEnv) -> io::Result<Vec<Socket>> {
+    let mut sockets = Vec::new();
+    for listener in listeners {
+        match listener {
+            Listener::Tcp(address) => {
+                sockets.push(Socket::Tcp(TcpListener::bind(address)?));
+            }
+            #[cfg(unix)]
+            Listener::Unix { path, mode } => {
+                sockets.push(Socket::Unix(unix::bind(path, *mode)?));
+            }
+            #[cfg(unix)]
+            Listener::Systemd => sockets.extend(unix::systemd(env)?),
+            #[cfg(not(unix))]
+            Listener::Unix { .. } | Listener::Systemd => {
+                return Err(io::Error::other(
+                    "Unix sockets and systemd sockets are only supported on Unix",
+                ));
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, DirBuilder, Permissions},
+        io,
+        net::TcpListener,
+        os::{
+            fd::{FromRawFd, OwnedFd, RawFd},
+            unix::{
+                fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+                net::UnixListener,
+            },
+        },
+        path::Path,
+    };
+
+    use tracing::warn;
+
+    use super::{ListenEnv, Socket};
+
+    /// The first socket passed by systemd.
+    const LISTEN_FDS_START: RawFd = 3;
+
+    pub fn bind(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+        // A previous run might have left its socket behind.
+        match fs::symlink_metadata(path) {
+            Ok(m) if m.file_type().is_socket() => fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(io::Error::other(format!(
+                    "{} exists and isn't a socket",
+                    path.display()
+                )))
+            }
+            Err(_) => (),
+        }
+        // The socket is created with the permissions of the umask, so it's bound in a private
+        // directory until it has its mode and then moved into place.
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::other(format!("{} isn't a file", path.display())))?;
+        let dir = path.with_file_name(format!(
+            ".{}.{}",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let res = bind_in(&dir, path, mode);
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            warn!(error = ?e, dir = %dir.display(), "Couldn't remove the socket directory");
+        }
+        res
+    }
+
+    fn bind_in(dir: &Path, path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+        let tmp = dir.join("socket");
+        let listener = UnixListener::bind(&tmp)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(&tmp, Permissions::from_mode(mode))?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(listener)
+    }
+
+    /// Takes the sockets passed by systemd, this can only be done once.
+    pub fn systemd(env: &ListenEnv) -> io::Result<Vec<Socket>> {
+        let count = env.count(std::process::id())?;
+
+        let sockets = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // SAFETY: systemd passes open sockets starting at fd 3, which nothing else owns.
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                let tcp = TcpListener::from(fd);
+                // Only TCP sockets have an IP address.
+                match tcp.local_addr() {
+                    Ok(_) => Socket::Tcp(tcp),
+                    Err(_) => Socket::Unix(UnixListener::from(OwnedFd::from(tcp))),
+                }
+            })
+            .collect();
+        Ok(sockets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Bind {
+        #[serde(deserialize_with = "one_or_many")]
+        bind: Vec<Listener>,
+    }
+
+    fn parse(toml: &str) -> Result<Vec<Listener>, toml::de::Error> {
+        toml::from_str::<Bind>(toml).map(|b| b.bind)
+    }
+
+    #[test]
+    fn parses_listeners() {
+        assert_eq!(
+            parse(r#"bind = "127.0.0.1:4833""#).unwrap(),
+            [Listener::Tcp("127.0.0.1:4833".to_owned())]
+        );
+        assert_eq!(
+            parse(
+                r#"bind = ["[::]:80", "systemd", { unix = "/run/uploader.sock", mode = 0o660 }, { unix = "a.sock" }]"#
+            )
+            .unwrap(),
+            [
+                Listener::Tcp("[::]:80".to_owned()),
+                Listener::Systemd,
+                Listener::Unix {
+                    path: "/run/uploader.sock".into(),
+                    mode: Some(0o660),
+                },
+                Listener::Unix {
+                    path: "a.sock".into(),
+                    mode: None,
+                },
+            ]
+        );
+        assert_eq!(
+            parse(r#"bind = "127.0.0.1:4833""#).unwrap()[0].port(),
+            Some(4833)
+        );
+        assert_eq!(parse(r#"bind = "systemd""#).unwrap()[0].port(), None);
+
+        let err = toml::from_str::<Listener>("unix = \"a.sock\"\nmode = 0o1777").unwrap_err();
+        assert!(err.to_string().contains("Invalid mode 0o1777"), "{err}");
+        assert!(parse(r#"bind = { unix = "a.sock", mode = 0o1777 }"#).is_err());
+        assert!(parse("bind = 80").is_err());
+        assert!(parse(r#"bind = { path = "a.sock" }"#).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_sockets_of_this_process() {
+        let env = |pid: &str, fds: &str| ListenEnv {
+            pid: Some(pid.to_owned()),
+            fds: Some(fds.to_owned()),
+        };
+        assert_eq!(env("42", "2").count(42).unwrap(), 2);
+        assert!(env("43", "2").count(42).is_err());
+        assert!(env("42", "two").count(42).is_err());
+        assert!(ListenEnv::default().count(42).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn binds_unix_sockets_with_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("uploader-listen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uploader.sock");
+        for _ in 0..2 {
+            // The second bind replaces the socket of the first.
+            unix::bind(&path, Some(0o600)).unwrap();
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Only the socket is left.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(unix::bind(&file, None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
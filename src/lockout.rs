@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    delete, get,
+    http::{header, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config, metrics,
+    ratelimit::{self, Limit, Subject},
+};
+
+/// Stale failures are only pruned if more than this many IPs are tracked.
+const PRUNE_THRESHOLD: usize = 1024;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+static FAILURES: Lazy<Mutex<Tracker>> = Lazy::new(|| {
+    Mutex::new(Tracker {
+        failures: HashMap::new(),
+        last_prune: Instant::now(),
+    })
+});
+
+/// The `[lockout]` section of the config, an IP is locked out after 10 failures by default.
+///
+/// Behind a proxy, `rate_limit.trusted_proxies` has to be set,
+/// otherwise all clients share the IP of the proxy and lock each other out.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LockoutConfig {
+    /// Failed authentications within the `window` until an IP is locked out, 0 disables lockouts.
+    pub max_failures: u32,
+    #[serde(deserialize_with = "config::from_humantime")]
+    pub window: Duration,
+    /// The first lockout, every further one is twice as long.
+    #[serde(deserialize_with = "config::from_humantime")]
+    pub duration: Duration,
+    #[serde(deserialize_with = "config::from_humantime")]
+    pub max_duration: Duration,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 10,
+            window: Duration::from_secs(15 * 60),
+            duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LockedOut {
+    retry_after: Duration,
+}
+
+impl fmt::Display for LockedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many failed authentications, try again in {} second(s)",
+            ratelimit::retry_after_secs(self.retry_after)
+        )
+    }
+}
+
+impl ResponseError for LockedOut {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header((
+                header::RETRY_AFTER,
+                ratelimit::retry_after_secs(self.retry_after),
+            ))
+            .json(serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+#[derive(Debug, thiserror::Error, actix_web_error::Json)]
+pub enum LockoutError {
+    #[error("This IP isn't tracked")]
+    #[status(404)]
+    NotFound,
+}
+
+struct Tracker {
+    failures: HashMap<IpAddr, Failures>,
+    last_prune: Instant,
+}
+
+impl Tracker {
+    /// Removes failures that can be forgotten.
+    fn prune(&mut self, config: &LockoutConfig, now: Instant) {
+        if self.failures.len() < PRUNE_THRESHOLD
+            || now.duration_since(self.last_prune) < PRUNE_INTERVAL
+        {
+            return;
+        }
+        self.last_prune = now;
+        self.failures.retain(|_, f| !f.is_stale(config, now));
+    }
+
+    fn locked_for(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        self.failures.get(&ip).and_then(|f| f.locked_for(now))
+    }
+
+    /// Records a failure and returns how long the IP is locked out if it failed too often.
+    fn failure(&mut self, config: &LockoutConfig, ip: IpAddr, now: Instant) -> Option<Duration> {
+        self.prune(config, now);
+        let entry = self.failures.entry(ip).or_insert(Failures {
+            count: 0,
+            last_failure: now,
+            lockouts: 0,
+            locked_until: None,
+        });
+        if now.duration_since(entry.last_failure) > config.window {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.last_failure = now;
+        if entry.count < config.max_failures {
+            return None;
+        }
+
+        let duration = config
+            .duration
+            .saturating_mul(2u32.saturating_pow(entry.lockouts))
+            .min(config.max_duration);
+        entry.count = 0;
+        entry.lockouts += 1;
+        entry.locked_until = Some(now + duration);
+        Some(duration)
+    }
+}
+
+struct Failures {
+    /// Failures since the last lockout.
+    count: u32,
+    last_failure: Instant,
+    /// How often the IP was locked out, each lockout doubles the next one.
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .map(|until| until.saturating_duration_since(now))
+            .filter(|d| !d.is_zero())
+    }
+
+    /// Whether the failures can be forgotten.
+    fn is_stale(&self, config: &LockoutConfig, now: Instant) -> bool {
+        self.locked_for(now).is_none()
+            && now.duration_since(self.last_failure) > config.window.max(config.max_duration)
+    }
+}
+
+/// Rejects IPs that are locked out.
+pub fn check(ip: IpAddr) -> Result<(), LockedOut> {
+    match FAILURES.lock().unwrap().locked_for(ip, Instant::now()) {
+        Some(retry_after) => Err(LockedOut { retry_after }),
+        None => Ok(()),
+    }
+}
+
+/// Records a failed authentication and locks the IP out if it failed too often.
+pub fn failure(ip: IpAddr) {
+    let config = &config::get().lockout;
+    if config.max_failures == 0 {
+        return;
+    }
+    let Some(duration) = FAILURES.lock().unwrap().failure(config, ip, Instant::now()) else {
+        return;
+    };
+    metrics::lockout();
+    warn!(
+        %ip,
+        duration = %humantime::format_duration(duration),
+        "Locked out IP after too many failed authentications"
+    );
+}
+
+/// Forgets the failures of an IP after it authenticated.
+pub fn success(ip: IpAddr) {
+    FAILURES.lock().unwrap().failures.remove(&ip);
+}
+
+/// The number of IPs that are currently locked out.
+pub fn locked_count() -> usize {
+    let now = Instant::now();
+    FAILURES
+        .lock()
+        .unwrap()
+        .failures
+        .values()
+        .filter(|f| f.locked_for(now).is_some())
+        .count()
+}
+
+#[derive(Serialize)]
+pub struct LockoutEntry {
+    ip: IpAddr,
+    /// Failures since the last lockout.
+    failures: u32,
+    lockouts: u32,
+    /// Seconds until the lockout ends, `null` if the IP isn't locked out.
+    locked_for: Option<u64>,
+}
+
+/// All IPs with recent failed authentications.
+#[get("/lockouts")]
+pub async fn list() -> HttpResponse {
+    let now = Instant::now();
+    let config = &config::get().lockout;
+    let mut entries = FAILURES
+        .lock()
+        .unwrap()
+        .failures
+        .iter()
+        .filter(|(_, f)| !f.is_stale(config, now))
+        .map(|(ip, f)| LockoutEntry {
+            ip: *ip,
+            failures: f.count,
+            lockouts: f.lockouts,
+            locked_for: f.locked_for(now).map(ratelimit::retry_after_secs),
+        })
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|e| e.ip);
+    HttpResponse::Ok().json(entries)
+}
+
+/// Forgets the failures of an IP, which lifts its lockout and resets its escalation.
+#[delete("/lockouts/{ip}")]
+pub async fn clear(ip: web::Path<IpAddr>) -> Result<HttpResponse, LockoutError> {
+    let ip = ip.into_inner();
+    ratelimit::reset(Limit::AuthFailure, Subject::Ip(ip));
+    match FAILURES.lock().unwrap().failures.remove(&ip) {
+        Some(_) => {
+            info!(%ip, "Cleared lockout");
+            Ok(HttpResponse::NoContent().finish())
+        }
+        None => Err(LockoutError::NotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+
+    fn tracker() -> Tracker {
+        Tracker {
+            failures: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    fn config() -> LockoutConfig {
+        LockoutConfig {
+            max_failures: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn escalates_lockouts() {
+        let config = config();
+        let mut tracker = tracker();
+        let mut now = Instant::now();
+        for expected in [1, 2, 4, 8] {
+            assert_eq!(tracker.failure(&config, IP, now), None);
+            assert_eq!(tracker.failure(&config, IP, now), None);
+            let duration = tracker.failure(&config, IP, now).unwrap();
+            assert_eq!(duration, Duration::from_secs(60 * expected));
+            assert_eq!(tracker.locked_for(IP, now), Some(duration));
+            now += duration;
+        }
+
+        let config = LockoutConfig {
+            max_duration: Duration::from_secs(5 * 60),
+            ..config
+        };
+        for _ in 0..3 {
+            tracker.failure(&config, IP, now);
+        }
+        assert_eq!(
+            tracker.locked_for(IP, now),
+            Some(Duration::from_secs(5 * 60))
+        );
+    }
+
+    #[test]
+    fn lockouts_and_failures_expire() {
+        let config = config();
+        let mut tracker = tracker();
+        let now = Instant::now();
+        tracker.failure(&config, IP, now);
+        tracker.failure(&config, IP, now);
+        // The first failures are outside of the window.
+        let later = now + config.window + Duration::from_secs(1);
+        assert_eq!(tracker.failure(&config, IP, later), None);
+        assert_eq!(tracker.locked_for(IP, later), None);
+
+        tracker.failure(&config, IP, later);
+        let duration = tracker.failure(&config, IP, later).unwrap();
+        assert!(tracker.locked_for(IP, later + duration / 2).is_some());
+        assert_eq!(tracker.locked_for(IP, later + duration), None);
+
+        let entry = &tracker.failures[&IP];
+        assert!(!entry.is_stale(&config, later + config.window));
+        assert!(entry.is_stale(
+            &config,
+            later + config.max_duration + Duration::from_secs(1)
+        ));
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        config::use_test_config();
+        let ip = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 99));
+        for _ in 0..LockoutConfig::default().max_failures {
+            failure(ip);
+        }
+        let err = check(ip).unwrap_err();
+        assert_eq!(
+            err.error_response()
+                .headers()
+                .get(header::RETRY_AFTER)
+                .unwrap(),
+            "60"
+        );
+        success(ip);
+        assert!(check(ip).is_ok());
+    }
+}
@@ -1,10 +1,8 @@
-use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
 use actix_web::{
-    dev::{fn_service, ServiceRequest, ServiceResponse},
-    get, http,
-    http::{header, StatusCode},
-    middleware::Compress,
+    dev::{fn_service, Server, ServiceRequest, ServiceResponse},
+    get, guard,
+    middleware::{self, Compress},
     web, App, HttpServer, Responder,
 };
 use args::Args;
@@ -14,38 +12,46 @@ use tracing_actix_web::TracingLogger;
 use tracing_subscriber::EnvFilter;
 
 use crate::{
-    auth::AuthRequirement,
-    config::CONFIG,
+    auth::{AuthRequirement, Scope},
+    cors::Cors,
     guards::MimeGuard,
-    templates::{audio_template, text_template},
+    listen::Socket,
+    ratelimit::{Limit, RateLimit},
+    templates::{audio_template, pdf_template, text_template},
     upload::{upload_multipart, upload_post},
 };
 
+mod admin;
 mod args;
+mod audit;
 mod auth;
 mod clean;
 mod config;
+mod cors;
 mod deletion;
+mod detect;
 mod guards;
+mod history;
+mod init;
+mod listen;
+mod lockout;
+mod meta;
+mod metrics;
+mod ratelimit;
+mod raw;
 mod rng;
+mod secrets;
+mod shutdown;
+mod svg;
 mod templates;
+mod tls;
+mod token;
 mod upload;
-
-async fn not_found_svc(req: ServiceRequest) -> Result<ServiceResponse, actix_web::Error> {
-    let (req, _) = req.into_parts();
-    let file = NamedFile::open_async("./static/404.html").await?;
-    let mut res = file.into_response(&req);
-    *res.status_mut() = StatusCode::NOT_FOUND;
-    Ok(ServiceResponse::new(req, res))
-}
+mod webhooks;
 
 async fn not_found_svc_short(req: ServiceRequest) -> Result<ServiceResponse, actix_web::Error> {
     let (req, _) = req.into_parts();
-    let file = NamedFile::open_async("./static/404.html")
-        .await?
-        .use_etag(false);
-    let mut res = file.into_response(&req);
-    *res.status_mut() = StatusCode::NOT_FOUND;
+    let res = raw::not_found(&req).await?;
     Ok(ServiceResponse::new(req, res))
 }
 
@@ -59,8 +65,19 @@ async fn favicon() -> impl Responder {
     NamedFile::open_async("./static/favicon.ico").await
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+fn static_files() -> Files {
+    Files::new("/static", "static")
+        .use_etag(false)
+        .default_handler(fn_service(not_found_svc_short))
+}
+
+fn main() -> std::io::Result<()> {
+    // The environment can only be changed safely before the runtime starts its threads.
+    let listen_env = listen::ListenEnv::take();
+    actix_web::rt::System::new().block_on(run(listen_env))
+}
+
+async fn run(listen_env: listen::ListenEnv) -> std::io::Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(
             EnvFilter::builder()
@@ -69,51 +86,115 @@ async fn main() -> std::io::Result<()> {
         )
         .init();
 
-    std::fs::create_dir_all(&CONFIG.file_dir).unwrap();
-
     let args = Args::parse();
+    config::set_path(args.config);
     if let Some(cmd) = args.cmd {
         return cmd.run();
     };
 
-    HttpServer::new(move || {
+    let config = config::get();
+    std::fs::create_dir_all(&config.file_dir).unwrap();
+    std::fs::create_dir_all(meta::dir()).unwrap();
+    // Webhooks might be added when the config is reloaded.
+    std::fs::create_dir_all(webhooks::dir()).unwrap();
+    actix_web::rt::spawn(webhooks::run());
+    actix_web::rt::spawn(config::watch());
+
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allowed_headers(&[header::ACCEPT, header::CONTENT_TYPE])
-                    .allowed_methods([http::Method::GET])
-                    .max_age(60 * 60),
-            )
+            .wrap(Cors)
             .wrap(TracingLogger::default())
             .wrap(Compress::default())
+            // The user content domain only serves the raw files.
+            .service(
+                web::scope("")
+                    .guard(guard::fn_guard(|ctx| {
+                        raw::is_user_content_host(&config::get(), ctx.head())
+                    }))
+                    .service(static_files())
+                    .service(favicon)
+                    .service(raw::service)
+                    .default_service(fn_service(not_found_svc_short)),
+            )
             .service(
                 web::resource("/upload")
                     .app_data(web::PayloadConfig::new(1024 * 1024 * 100)) // 100MB
-                    .wrap(AuthRequirement::new(&CONFIG.authorization))
+                    .wrap(RateLimit::new(Limit::Upload))
+                    .wrap(AuthRequirement::new(Scope::Upload))
+                    .wrap(middleware::from_fn(shutdown::reject_uploads))
                     .route(web::post().guard(MimeGuard).to(upload_multipart))
                     .route(web::post().to(upload_post)),
             )
             .service(
-                Files::new("/static", "static")
-                    .use_etag(false)
-                    .default_handler(fn_service(not_found_svc)),
+                web::scope("/api")
+                    .wrap(AuthRequirement::authenticated())
+                    .service(history::history)
+                    .service(deletion::delete)
+                    .service(deletion::revoke)
+                    .service(deletion::reissue)
+                    .service(
+                        web::scope("/admin")
+                            .wrap(AuthRequirement::new(Scope::Admin))
+                            .service(admin::list)
+                            .service(admin::stats)
+                            .service(admin::detail)
+                            .service(admin::bulk_delete)
+                            .service(lockout::list)
+                            .service(lockout::clear),
+                    ),
             )
+            .configure(metrics::configure)
+            .service(static_files())
             .service(deletion::service)
             .service(deletion::view)
             .service(audio_template)
             .service(text_template)
+            .service(pdf_template)
             .service(index)
+            .service(admin::dashboard)
             .service(favicon)
-            .service(
-                Files::new("/", &CONFIG.file_dir)
-                    .use_etag(true)
-                    .use_last_modified(true)
-                    .prefer_utf8(true)
-                    .default_handler(fn_service(not_found_svc_short)),
-            )
+            .service(raw::service)
+            .default_service(fn_service(not_found_svc_short))
     })
-    .bind(&CONFIG.bind)?
-    .run()
-    .await
+    .shutdown_timeout(config.shutdown_timeout.as_secs())
+    .disable_signals();
+    let tls = config.tls.as_ref().map(tls::server_config).transpose()?;
+    let mut server = server;
+    for socket in listen::open(&config.bind, &listen_env)? {
+        server = match (socket, &tls) {
+            (Socket::Tcp(listener), Some((tls_config, _))) => {
+                server.listen_rustls_0_23(listener, tls_config.clone())?
+            }
+            (Socket::Tcp(listener), None) => server.listen(listener)?,
+            // A proxy in front of a Unix socket handles TLS.
+            #[cfg(unix)]
+            (Socket::Unix(listener), _) => server.listen_uds(listener)?,
+        };
+    }
+    let server = server.run();
+
+    let redirect = match config.tls.as_ref().and_then(|tls| tls.redirect.as_ref()) {
+        Some(redirect) => Some(
+            HttpServer::new(|| App::new().default_service(web::to(tls::redirect)))
+                .disable_signals()
+                .bind(redirect)?
+                .run(),
+        ),
+        None => None,
+    };
+    if let Some((_, watch_certificate)) = tls {
+        actix_web::rt::spawn(watch_certificate);
+    }
+    let handles = [Some(&server), redirect.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(Server::handle)
+        .collect();
+    actix_web::rt::spawn(shutdown::on_signal(handles));
+    shutdown::notify("READY=1");
+
+    match redirect {
+        Some(redirect) => futures::try_join!(server, redirect).map(|_| ()),
+        None => server.await,
+    }
 }
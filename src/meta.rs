@@ -0,0 +1,207 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::config;
+
+type UploaderIndex = HashMap<String, HashSet<String>>;
+
+/// The uploads of every key or token, so they can be listed without reading all metadata.
+///
+/// It's built from the metadata when it's first needed.
+/// Files removed by other processes (like `clean`) stay in it until they're listed.
+static UPLOADERS: OnceCell<Mutex<UploaderIndex>> = OnceCell::const_new();
+
+/// Information about an upload that can't be derived from the file itself on every request.
+///
+/// It's stored as JSON in a hidden directory inside `file_dir`,
+/// which isn't served by the file service.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FileMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdf: Option<PdfMeta>,
+    /// The encoding of text files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    /// The name of the key used to upload the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    /// Incremented when the deletion keys are revoked, older keys are invalid.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub deletion_generation: u32,
+    /// The hex SHA-256 of the file, for the audit log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PdfMeta {
+    pub title: Option<String>,
+    pub pages: u32,
+}
+
+pub fn dir() -> PathBuf {
+    config::get().file_dir.join(".meta")
+}
+
+fn path(filename: &str) -> PathBuf {
+    dir().join(format!("{filename}.json"))
+}
+
+pub async fn read(filename: &str) -> io::Result<Option<FileMeta>> {
+    match tokio::fs::read(path(filename)).await {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn write(filename: &str, meta: &FileMeta) -> io::Result<()> {
+    let data =
+        serde_json::to_vec(meta).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    tokio::fs::write(path(filename), data).await?;
+    if let Some(uploader) = &meta.uploader {
+        // A failed build is retried with the new file on the next use.
+        match uploaders().await {
+            Ok(index) => {
+                index
+                    .lock()
+                    .unwrap()
+                    .entry(uploader.clone())
+                    .or_default()
+                    .insert(filename.to_owned());
+            }
+            Err(e) => warn!(error = ?e, "Couldn't index uploads"),
+        }
+    }
+    Ok(())
+}
+
+pub async fn remove(filename: &str) -> io::Result<()> {
+    if let Some(index) = UPLOADERS.get() {
+        for files in index.lock().unwrap().values_mut() {
+            files.remove(filename);
+        }
+    }
+    match tokio::fs::remove_file(path(filename)).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The files uploaded with a key or token, which can include files that were removed since.
+pub async fn uploads_of(uploader: &str) -> io::Result<HashSet<String>> {
+    let index = uploaders().await?.lock().unwrap();
+    Ok(index.get(uploader).cloned().unwrap_or_default())
+}
+
+async fn uploaders() -> io::Result<&'static Mutex<UploaderIndex>> {
+    UPLOADERS
+        .get_or_try_init(|| async {
+            let dir = dir();
+            tokio::task::spawn_blocking(move || build_index(&dir))
+                .await
+                .map_err(io::Error::other)?
+                .map(Mutex::new)
+        })
+        .await
+}
+
+fn build_index(dir: &Path) -> io::Result<UploaderIndex> {
+    let mut index = UploaderIndex::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(index),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Some(filename) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.strip_suffix(".json"))
+            .map(str::to_owned)
+        else {
+            continue;
+        };
+        let meta = std::fs::read(entry.path()).and_then(|data| {
+            serde_json::from_slice::<FileMeta>(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        });
+        match meta {
+            Ok(FileMeta {
+                uploader: Some(uploader),
+                ..
+            }) => {
+                index.entry(uploader).or_default().insert(filename);
+            }
+            Ok(_) => (),
+            Err(e) => warn!(error = ?e, file = filename, "Couldn't read metadata"),
+        }
+    }
+    Ok(index)
+}
+
+/// Blocking version of [`read`] for use in subcommands.
+pub fn read_sync(filename: &str) -> io::Result<Option<FileMeta>> {
+    match std::fs::read(path(filename)) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Blocking version of [`remove`] for use in subcommands.
+pub fn remove_sync(filename: &str) -> io::Result<()> {
+    match std::fs::remove_file(path(filename)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_uploaders() {
+        let dir = std::env::temp_dir().join(format!("uploader-meta-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("a.png.json", r#"{"uploader":"sharex"}"#),
+            ("b.txt.json", r#"{"uploader":"sharex","charset":"UTF-8"}"#),
+            ("c.pdf.json", r#"{"uploader":"contractor"}"#),
+            ("d.png.json", "{}"),
+            ("e.png.json", "invalid"),
+            ("notes.txt", r#"{"uploader":"sharex"}"#),
+        ];
+        for (name, data) in files {
+            std::fs::write(dir.join(name), data).unwrap();
+        }
+
+        let index = build_index(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(
+            index["sharex"],
+            HashSet::from(["a.png".to_owned(), "b.txt".to_owned()])
+        );
+        assert_eq!(index["contractor"], HashSet::from(["c.pdf".to_owned()]));
+        assert!(build_index(&dir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    admin,
+    auth::{AuthRequirement, Scope},
+    config,
+    detect::{MismatchPolicy, TypeHint},
+    lockout,
+};
+
+/// Who can access `/metrics`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsAccess {
+    /// Don't serve the metrics.
+    #[default]
+    Disabled,
+    /// Everyone.
+    Public,
+    /// Keys and tokens with the `metrics` scope.
+    Authenticated,
+}
+
+static UPLOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "uploader_uploads_total",
+        "Successful uploads",
+        &["type", "key"]
+    )
+    .unwrap()
+});
+static UPLOAD_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "uploader_upload_bytes_total",
+        "Size of successful uploads",
+        &["type", "key"]
+    )
+    .unwrap()
+});
+static UPLOAD_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "uploader_upload_duration_seconds",
+        "Time taken to receive and store successful uploads",
+        &["type"],
+        vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+static UPLOAD_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("uploader_upload_errors_total", "Failed uploads", &["error"]).unwrap()
+});
+static TYPE_MISMATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "uploader_type_mismatches_total",
+        "Uploads whose content didn't match the specified type",
+        &["policy"]
+    )
+    .unwrap()
+});
+static DELETIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("uploader_deletions_total", "Deleted uploads", &["via"]).unwrap()
+});
+static DELETION_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "uploader_deletion_errors_total",
+        "Failed deletions",
+        &["error"]
+    )
+    .unwrap()
+});
+static DOWNLOADS: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("uploader_downloads_total", "Raw files served").unwrap());
+static NOT_FOUND: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("uploader_not_found_total", "Requests answered with a 404").unwrap()
+});
+static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "uploader_rate_limited_total",
+        "Requests rejected by a rate limit",
+        &["limit"]
+    )
+    .unwrap()
+});
+static LOCKOUTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "uploader_lockouts_total",
+        "IPs locked out after failed authentications"
+    )
+    .unwrap()
+});
+static LOCKED_OUT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "uploader_locked_out_ips",
+        "IPs that are currently locked out"
+    )
+    .unwrap()
+});
+static DISK_USAGE: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("uploader_disk_usage_bytes", "Size of all uploads").unwrap());
+static FILES: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("uploader_files", "Number of uploads").unwrap());
+
+pub fn upload(hint: TypeHint, key: &str, size: u64, duration: Duration) {
+    let hint = hint.as_str();
+    UPLOADS.with_label_values(&[hint, key]).inc();
+    UPLOAD_BYTES.with_label_values(&[hint, key]).inc_by(size);
+    UPLOAD_DURATION
+        .with_label_values(&[hint])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn upload_error(error: &'static str) {
+    UPLOAD_ERRORS.with_label_values(&[error]).inc();
+}
+
+/// Only counts allowed uploads, rejected ones are `upload_error`s.
+pub fn type_mismatch(policy: MismatchPolicy) {
+    TYPE_MISMATCHES.with_label_values(&[policy.as_str()]).inc();
+}
+
+/// `via` is `key` (deletion link), `api` or `admin`.
+pub fn deletion(via: &'static str) {
+    DELETIONS.with_label_values(&[via]).inc();
+}
+
+pub fn deletion_error(error: &'static str) {
+    DELETION_ERRORS.with_label_values(&[error]).inc();
+}
+
+pub fn download() {
+    DOWNLOADS.inc();
+}
+
+pub fn not_found() {
+    NOT_FOUND.inc();
+}
+
+pub fn rate_limited(limit: &'static str) {
+    RATE_LIMITED.with_label_values(&[limit]).inc();
+}
+
+pub fn lockout() {
+    LOCKOUTS.inc();
+}
+
+/// Registers `/metrics` depending on the configured access.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    match config::get().metrics {
+        MetricsAccess::Disabled => (),
+        MetricsAccess::Public => {
+            cfg.route("/metrics", web::get().to(service));
+        }
+        MetricsAccess::Authenticated => {
+            cfg.service(
+                web::resource("/metrics")
+                    .wrap(AuthRequirement::new(Scope::Metrics))
+                    .route(web::get().to(service)),
+            );
+        }
+    }
+}
+
+async fn service() -> HttpResponse {
+    // The disk usage is only needed when scraping.
+    match admin::read_uploads().await {
+        Ok(uploads) => {
+            FILES.set(uploads.len() as i64);
+            DISK_USAGE.set(uploads.iter().map(|u| u.size as i64).sum());
+        }
+        Err(e) => warn!(error = ?e, "Couldn't read uploads for metrics"),
+    }
+
+    LOCKED_OUT.set(lockout::locked_count() as i64);
+
+    let encoder = TextEncoder::new();
+    match encoder.encode_to_string(&prometheus::gather()) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(body),
+        Err(e) => {
+            warn!(error = ?e, "Couldn't encode metrics");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    future::{ready, Ready},
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{self, HeaderMap},
+        StatusCode,
+    },
+    FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use futures::future::Either;
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer};
+
+use crate::{auth::Identity, config, metrics};
+
+/// Buckets are only pruned if there are more than this many.
+const PRUNE_THRESHOLD: usize = 1024;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+static BUCKETS: Lazy<Mutex<Buckets>> = Lazy::new(|| {
+    Mutex::new(Buckets {
+        buckets: HashMap::new(),
+        last_prune: Instant::now(),
+    })
+});
+
+/// The `[rate_limit]` section of the config, all limits are disabled by default.
+#[derive(Default, Deserialize)]
+pub struct RateLimitConfig {
+    /// Proxies whose `X-Forwarded-For` header is used to find the client (IPs or CIDR ranges).
+    #[serde(default, deserialize_with = "from_ips_or_nets")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Uploads per IP.
+    pub upload: Option<Budget>,
+    /// Uploads per key or token.
+    pub upload_per_key: Option<Budget>,
+    /// Failed authentications per IP.
+    pub auth_failures: Option<Budget>,
+    /// Raw files served per IP.
+    pub download: Option<Budget>,
+}
+
+/// A token bucket, which is refilled continuously.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Budget {
+    pub per_minute: u32,
+    /// The maximum number of requests at once.
+    pub burst: u32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Limit {
+    Upload,
+    UploadPerKey,
+    AuthFailure,
+    Download,
+}
+
+impl Limit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::UploadPerKey => "upload_per_key",
+            Self::AuthFailure => "auth_failure",
+            Self::Download => "download",
+        }
+    }
+
+    fn budget(self) -> Option<Budget> {
+        let config = &config::get().rate_limit;
+        match self {
+            Self::Upload => config.upload,
+            Self::UploadPerKey => config.upload_per_key,
+            Self::AuthFailure => config.auth_failures,
+            Self::Download => config.download,
+        }
+    }
+}
+
+/// Who a bucket belongs to.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Subject {
+    Ip(IpAddr),
+    Key(String),
+}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many requests, try again in {} second(s)",
+            retry_after_secs(self.retry_after)
+        )
+    }
+}
+
+impl ResponseError for RateLimited {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after_secs(self.retry_after)))
+            .json(serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// Retry-After is in whole seconds, rounded up so clients don't retry too early.
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil() as u64
+}
+
+struct Buckets {
+    buckets: HashMap<(Limit, Subject), Bucket>,
+    last_prune: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, budget: Budget, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(budget.per_minute) / 60.0).min(budget.burst.into());
+        self.updated = now;
+    }
+
+    /// The time until there's a whole token.
+    fn retry_after(&self, budget: Budget) -> Duration {
+        let missing = 1.0 - self.tokens;
+        Duration::from_secs_f64(missing * 60.0 / f64::from(budget.per_minute.max(1)))
+    }
+}
+
+/// Takes a token from the bucket of the subject.
+pub fn take(limit: Limit, subject: Subject) -> Result<(), RateLimited> {
+    update(limit, subject, true)
+}
+
+/// Checks if the bucket has a token without taking it.
+pub fn check(limit: Limit, subject: Subject) -> Result<(), RateLimited> {
+    update(limit, subject, false)
+}
+
+/// Refills the bucket of the subject.
+pub fn reset(limit: Limit, subject: Subject) {
+    BUCKETS.lock().unwrap().buckets.remove(&(limit, subject));
+}
+
+fn update(limit: Limit, subject: Subject, take: bool) -> Result<(), RateLimited> {
+    let Some(budget) = limit.budget() else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let mut buckets = BUCKETS.lock().unwrap();
+    buckets.prune(now);
+    let bucket = buckets
+        .buckets
+        .entry((limit, subject))
+        .or_insert_with(|| Bucket {
+            tokens: budget.burst.into(),
+            updated: now,
+        });
+    bucket.refill(budget, now);
+    if bucket.tokens < 1.0 {
+        metrics::rate_limited(limit.as_str());
+        return Err(RateLimited {
+            retry_after: bucket.retry_after(budget),
+        });
+    }
+    if take {
+        bucket.tokens -= 1.0;
+    }
+    Ok(())
+}
+
+impl Buckets {
+    /// Removes full buckets, they're the same as new ones.
+    fn prune(&mut self, now: Instant) {
+        if self.buckets.len() < PRUNE_THRESHOLD
+            || now.duration_since(self.last_prune) < PRUNE_INTERVAL
+        {
+            return;
+        }
+        self.last_prune = now;
+        self.buckets.retain(|(limit, _), bucket| {
+            let Some(budget) = limit.budget() else {
+                return false;
+            };
+            bucket.refill(budget, now);
+            bucket.tokens < budget.burst.into()
+        });
+    }
+}
+
+/// The IP of the client, taken from `X-Forwarded-For` if the peer is a trusted proxy.
+///
+/// Connections over Unix sockets have no peer, they're treated as coming from a trusted proxy.
+pub fn client_ip(peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    let peer = peer.map(|p| p.ip());
+    let trusted = &config::get().rate_limit.trusted_proxies;
+    if peer.is_some_and(|peer| !trusted.iter().any(|net| net.contains(&peer))) {
+        return peer;
+    }
+    // The rightmost untrusted address was added by a trusted proxy.
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted.iter().any(|net| net.contains(ip)))
+        .or(peer)
+}
+
+/// Extracts the [`client_ip`] of a request.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequest for ClientIp {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self(client_ip(req.peer_addr(), req.headers()))))
+    }
+}
+
+/// Takes a token for every request, uploads also take one from the bucket of the key.
+///
+/// This has to be wrapped by the [`AuthRequirement`](crate::auth::AuthRequirement) for uploads.
+pub struct RateLimit {
+    limit: Limit,
+}
+
+impl RateLimit {
+    pub fn new(limit: Limit) -> Self {
+        Self { limit }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(Self::Transform {
+            service,
+            limit: self.limit,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limit: Limit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let mut res = match client_ip(req.peer_addr(), req.headers()) {
+            Some(ip) => take(self.limit, Subject::Ip(ip)),
+            None => Ok(()),
+        };
+        if self.limit == Limit::Upload {
+            let key = req.extensions().get::<Identity>().map(|i| i.name.clone());
+            if let Some(key) = key {
+                res = res.and_then(|_| take(Limit::UploadPerKey, Subject::Key(key)));
+            }
+        }
+        match res {
+            Ok(()) => Either::Right(self.service.call(req)),
+            Err(e) => Either::Left(ready(Err(e.into()))),
+        }
+    }
+}
+
+fn from_ips_or_nets<'de, D>(de: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderValue;
+
+    use super::*;
+
+    const BUDGET: Budget = Budget {
+        per_minute: 6,
+        burst: 2,
+    };
+
+    #[test]
+    fn refills_up_to_burst() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+        bucket.refill(BUDGET, start + Duration::from_secs(5));
+        assert!((bucket.tokens - 0.5).abs() < 1e-9);
+        bucket.refill(BUDGET, start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        let bucket = Bucket {
+            tokens: 0.75,
+            updated: Instant::now(),
+        };
+        // A token every 10s, a quarter is missing.
+        assert_eq!(bucket.retry_after(BUDGET), Duration::from_millis(2500));
+        let limited = RateLimited {
+            retry_after: bucket.retry_after(BUDGET),
+        };
+        let res = limited.error_response();
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "3");
+        assert!(limited.to_string().contains("in 3 second(s)"));
+
+        assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+        assert_eq!(retry_after_secs(Duration::from_secs(2)), 2);
+    }
+
+    #[test]
+    fn client_ip_from_trusted_proxies() {
+        config::use_test_config();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_static("203.0.113.7, 198.51.100.1, 10.0.0.2"),
+        );
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let peer = |s: &str| Some(SocketAddr::new(ip(s), 1234));
+
+        // The rightmost address that isn't a trusted proxy.
+        assert_eq!(
+            client_ip(peer("10.0.0.1"), &headers),
+            Some(ip("198.51.100.1"))
+        );
+        // Unix sockets are trusted.
+        assert_eq!(client_ip(None, &headers), Some(ip("198.51.100.1")));
+        // Untrusted peers can't choose their IP.
+        assert_eq!(
+            client_ip(peer("192.0.2.1"), &headers),
+            Some(ip("192.0.2.1"))
+        );
+        // Without the header, it's the proxy itself.
+        assert_eq!(
+            client_ip(peer("10.0.0.1"), &HeaderMap::new()),
+            Some(ip("10.0.0.1"))
+        );
+    }
+}
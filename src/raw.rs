@@ -0,0 +1,185 @@
+use actix_files::NamedFile;
+use actix_web::{
+    dev::RequestHead,
+    http::{
+        header::{self, ContentDisposition, DispositionType},
+        StatusCode,
+    },
+    route, web, HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    config::{self, Config},
+    meta, metrics,
+    ratelimit::{Limit, RateLimit},
+};
+
+const SANDBOX_CSP: &str = "sandbox; default-src 'none'; style-src 'unsafe-inline'; img-src data:";
+
+/// How files that can run scripts (HTML, SVG, XML, JS) are served.
+///
+/// These would otherwise run on the same origin as the home page,
+/// which has access to the upload token.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActiveContentPolicy {
+    /// Force a download with `Content-Disposition: attachment`.
+    Attachment,
+    /// Serve the file as `text/plain`.
+    Text,
+    /// Add a `Content-Security-Policy: sandbox` header.
+    #[default]
+    Sandbox,
+    /// Only serve them on `user_content_domain`, without any changes.
+    Redirect,
+}
+
+/// Serves the uploaded files from `file_dir`.
+#[route(
+    "/{filename}",
+    method = "GET",
+    method = "HEAD",
+    wrap = "RateLimit::new(Limit::Download)"
+)]
+pub async fn service(
+    req: HttpRequest,
+    filename: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if filename.starts_with('.') || filename.contains(['/', '\\']) {
+        return not_found(&req).await;
+    }
+    let config = config::get();
+    if let Some(domain) = &config.user_content_domain {
+        if !is_user_content_host(&config, req.head()) {
+            return Ok(HttpResponse::TemporaryRedirect()
+                .insert_header((header::LOCATION, format!("{domain}/{filename}")))
+                .finish());
+        }
+    }
+    let file = match NamedFile::open_async(config.file_dir.join(filename.as_str())).await {
+        Ok(f) => f,
+        Err(_) => return not_found(&req).await,
+    };
+    let mut file = file
+        .use_etag(true)
+        .use_last_modified(true)
+        .prefer_utf8(true);
+
+    // PDFs are embedded in the PDF view, browsers would download them otherwise.
+    if file.content_type() == &mime::APPLICATION_PDF {
+        let disposition = ContentDisposition {
+            disposition: DispositionType::Inline,
+            parameters: file.content_disposition().parameters.clone(),
+        };
+        file = file.set_content_disposition(disposition);
+    }
+
+    let policy = is_active(file.content_type()).then_some(config.active_content);
+    if policy == Some(ActiveContentPolicy::Attachment) {
+        let disposition = ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: file.content_disposition().parameters.clone(),
+        };
+        file = file.set_content_disposition(disposition);
+    }
+
+    let essence = match policy {
+        Some(ActiveContentPolicy::Text) => "text/plain",
+        _ => file.content_type().essence_str(),
+    };
+    let charset = charset(&filename).await;
+    let content_type = match (essence, charset) {
+        // text files with unknown extensions
+        ("application/octet-stream", Some(charset)) => {
+            Some(format!("text/plain; charset={charset}"))
+        }
+        (essence, Some(charset)) => Some(format!("{essence}; charset={charset}")),
+        (_, None) if policy == Some(ActiveContentPolicy::Text) => {
+            Some("text/plain; charset=utf-8".to_owned())
+        }
+        _ => None,
+    };
+    if let Some(ct) = content_type {
+        match ct.parse() {
+            Ok(ct) => file = file.set_content_type(ct),
+            Err(e) => warn!(error = ?e, content_type = %ct, "Invalid content type"),
+        }
+    }
+
+    let mut res = file.into_response(&req);
+    let headers = res.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    if policy == Some(ActiveContentPolicy::Sandbox) {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            header::HeaderValue::from_static(SANDBOX_CSP),
+        );
+    }
+    metrics::download();
+    Ok(res)
+}
+
+/// Checks if a file can run scripts when opened in the browser.
+fn is_active(mime: &mime::Mime) -> bool {
+    mime.suffix() == Some(mime::XML)
+        || matches!(
+            mime.essence_str(),
+            "text/html"
+                | "text/xml"
+                | "text/javascript"
+                | "text/ecmascript"
+                | "application/xml"
+                | "application/javascript"
+                | "application/ecmascript"
+        )
+}
+
+/// Checks if a request was made to the host of `user_content_domain`.
+pub fn is_user_content_host(config: &Config, head: &RequestHead) -> bool {
+    let Some(domain) = &config.user_content_domain else {
+        return false;
+    };
+    let expected = domain
+        .split_once("://")
+        .map_or(domain.as_str(), |(_, rest)| rest)
+        .trim_end_matches('/');
+    let host = head
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| head.uri.authority().map(|a| a.as_str()));
+    host.is_some_and(|h| h.eq_ignore_ascii_case(expected))
+}
+
+/// The URL of an uploaded file to use in views.
+pub fn file_src(filename: &str) -> String {
+    match &config::get().user_content_domain {
+        Some(domain) => format!("{domain}/{filename}"),
+        None => format!("/{filename}"),
+    }
+}
+
+async fn charset(filename: &str) -> Option<String> {
+    match meta::read(filename).await {
+        Ok(meta) => meta.and_then(|m| m.charset),
+        Err(e) => {
+            warn!(error = ?e, file = %filename, "Couldn't read metadata");
+            None
+        }
+    }
+}
+
+pub async fn not_found(req: &HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+    metrics::not_found();
+    let file = NamedFile::open_async("./static/404.html")
+        .await?
+        .use_etag(false);
+    let mut res = file.into_response(req);
+    *res.status_mut() = StatusCode::NOT_FOUND;
+    Ok(res)
+}
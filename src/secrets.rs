@@ -0,0 +1,185 @@
+use std::io;
+
+use base64::Engine;
+use hmac::{Hmac, KeyInit, Mac};
+use rand::Rng;
+use sha2::Sha224;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Table};
+
+use crate::config::{self, Secret, SECRET_LEN};
+
+type HmacSha224 = Hmac<Sha224>;
+
+/// The length of a signature made before secrets had IDs.
+const UNVERSIONED_LEN: usize = 28;
+
+/// Signs the concatenated `parts` with the current secret.
+///
+/// The signature starts with the ID of the secret.
+pub fn sign(parts: &[&[u8]]) -> Vec<u8> {
+    let config = config::get();
+    let secret = config.signing_secret();
+    let mut signature = Vec::with_capacity(UNVERSIONED_LEN + 1);
+    signature.push(secret.id);
+    signature.extend_from_slice(&mac(&secret.secret, parts).finalize().into_bytes());
+    signature
+}
+
+/// Checks a signature made with any secret that isn't retired.
+///
+/// Signatures without an ID were made with the secret `0`.
+pub fn verify(parts: &[&[u8]], signature: &[u8]) -> bool {
+    let (id, signature) = match signature.len() {
+        UNVERSIONED_LEN => (0, signature),
+        _ => match signature.split_first() {
+            Some((id, signature)) => (*id, signature),
+            None => return false,
+        },
+    };
+    match config::get().secret(id) {
+        Some(secret) => mac(secret, parts).verify_slice(signature).is_ok(),
+        None => false,
+    }
+}
+
+fn mac(secret: &Secret, parts: &[&[u8]]) -> HmacSha224 {
+    let mut mac = HmacSha224::new(secret);
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// A new random secret as base64.
+pub fn generate() -> String {
+    let mut secret = [0; SECRET_LEN];
+    rand::rng().fill_bytes(&mut secret);
+    base64::engine::general_purpose::STANDARD.encode(secret)
+}
+
+/// Adds a new secret to the config, which will be used for new signatures.
+pub fn add() -> io::Result<()> {
+    let id = config::get()
+        .secrets
+        .iter()
+        .map(|s| s.id)
+        .chain(config::get().retired_secrets.iter().copied())
+        .max()
+        .map_or(Some(0), |id| id.checked_add(1))
+        .ok_or_else(|| io::Error::other("All secret IDs are used"))?;
+
+    let mut table = Table::new();
+    table["id"] = value(i64::from(id));
+    table["secret"] = value(generate());
+    edit_config(|doc| {
+        doc.entry("secrets")
+            .or_insert_with(|| ArrayOfTables::new().into())
+            .as_array_of_tables_mut()
+            .ok_or_else(|| io::Error::other("`secrets` must be an array of tables"))?
+            .push(table);
+        Ok(())
+    })?;
+    println!("Added secret {id}, it will be used once the server reloads the config");
+    Ok(())
+}
+
+/// Stops accepting signatures made with a secret.
+pub fn retire(id: u8) -> io::Result<()> {
+    if config::get().secret(id).is_none() {
+        return Err(io::Error::other(format!(
+            "There's no active secret with the ID {id}"
+        )));
+    }
+    if config::get().signing_secret().id == id {
+        return Err(io::Error::other(
+            "The newest secret can't be retired, add a new one first",
+        ));
+    }
+    edit_config(|doc| {
+        doc.entry("retired_secrets")
+            .or_insert_with(|| value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| io::Error::other("`retired_secrets` must be an array"))?
+            .push(i64::from(id));
+        Ok(())
+    })?;
+    println!("Retired secret {id}, it will be rejected once the server reloads the config");
+    Ok(())
+}
+
+pub fn list() -> io::Result<()> {
+    let signing = config::get().signing_secret().id;
+    let mut secrets: Vec<_> = config::get().secrets.iter().map(|s| s.id).collect();
+    secrets.sort_unstable();
+    for id in secrets {
+        if id == signing {
+            println!("{id} (signing)");
+        } else if config::get().is_retired(id) {
+            println!("{id} (retired)");
+        } else {
+            println!("{id}");
+        }
+    }
+    Ok(())
+}
+
+fn edit_config(edit: impl FnOnce(&mut DocumentMut) -> io::Result<()>) -> io::Result<()> {
+    let mut doc: DocumentMut = std::fs::read_to_string(config::path())?
+        .parse()
+        .map_err(io::Error::other)?;
+    edit(&mut doc)?;
+    std::fs::write(config::path(), doc.to_string())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Signs with the secret of the test config made of the bytes `first..first + 28`.
+    pub fn sign_with(id: Option<u8>, first: u8, parts: &[&[u8]]) -> Vec<u8> {
+        // The rest of the key is zeros, like for the configured secrets.
+        let mut secret = Secret::default();
+        for (i, byte) in secret[..SECRET_LEN].iter_mut().enumerate() {
+            *byte = first + i as u8;
+        }
+        id.into_iter()
+            .chain(mac(&secret, parts).finalize().into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn signs_with_newest_secret() {
+        config::use_test_config();
+        let signature = sign(&[b"a", b"b"]);
+        assert_eq!(signature, sign_with(Some(1), 100, &[b"ab"]));
+        assert!(verify(&[b"ab"], &signature));
+        assert!(!verify(&[b"abc"], &signature));
+    }
+
+    #[test]
+    fn verifies_unversioned_signatures_with_secret_0() {
+        config::use_test_config();
+        let signature = sign_with(None, 0, &[b"file.png"]);
+        assert_eq!(signature.len(), UNVERSIONED_LEN);
+        assert!(verify(&[b"file.png"], &signature));
+        assert!(verify(
+            &[b"file.png"],
+            &sign_with(Some(0), 0, &[b"file.png"])
+        ));
+    }
+
+    #[test]
+    fn rejects_retired_and_unknown_secrets() {
+        config::use_test_config();
+        assert!(!verify(
+            &[b"file.png"],
+            &sign_with(Some(2), 200, &[b"file.png"])
+        ));
+        // A valid signature with the wrong ID.
+        let mut signature = sign_with(Some(1), 100, &[b"file.png"]);
+        signature[0] = 9;
+        assert!(!verify(&[b"file.png"], &signature));
+        assert!(!verify(&[b"file.png"], &[]));
+        assert!(!verify(&[b"file.png"], &[1]));
+    }
+}
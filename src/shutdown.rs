@@ -0,0 +1,152 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServerHandle, ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+use tracing::{info, warn};
+
+use crate::config;
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Debug, thiserror::Error, actix_web_error::Json)]
+pub enum ShutdownError {
+    #[error("The server is shutting down, try again later")]
+    #[status(503)]
+    ShuttingDown,
+}
+
+/// Counts an upload as running until it's dropped.
+pub struct RunningUpload(());
+
+impl RunningUpload {
+    pub fn start() -> Self {
+        UPLOADS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for RunningUpload {
+    fn drop(&mut self) {
+        UPLOADS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Rejects new uploads once the server is shutting down.
+pub async fn reject_uploads(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if STOPPING.load(Ordering::Relaxed) {
+        return Err(ShutdownError::ShuttingDown.into());
+    }
+    next.call(req).await
+}
+
+/// Stops the servers on SIGTERM or Ctrl+C.
+///
+/// Running requests get `shutdown_timeout` to finish, uploads that don't are cancelled.
+pub async fn on_signal(servers: Vec<ServerHandle>) {
+    wait_for_signal().await;
+    STOPPING.store(true, Ordering::Relaxed);
+    notify("STOPPING=1");
+    info!(
+        uploads = UPLOADS.load(Ordering::Relaxed),
+        timeout = %humantime::format_duration(config::get().shutdown_timeout),
+        "Shutting down"
+    );
+    futures::future::join_all(servers.iter().map(|s| s.stop(true))).await;
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => (),
+                _ = tokio::signal::ctrl_c() => (),
+            },
+            Err(e) => {
+                warn!(error = ?e, "Couldn't listen for SIGTERM");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Sends a state like `READY=1` to systemd if it started the server with `Type=notify`.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send_notification(&path, state) {
+            warn!(error = ?e, state, "Couldn't notify systemd");
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send_notification(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test as actix_test, web, App, HttpResponse};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn rejects_uploads_while_stopping() {
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(reject_uploads))
+                .route("/upload", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let upload = || actix_test::TestRequest::post().uri("/upload").to_request();
+
+        assert_eq!(actix_test::call_service(&app, upload()).await.status(), 200);
+
+        STOPPING.store(true, Ordering::Relaxed);
+        let res = actix_test::try_call_service(&app, upload()).await;
+        STOPPING.store(false, Ordering::Relaxed);
+        let Err(err) = res else {
+            panic!("The upload was accepted");
+        };
+        let res = err.error_response();
+        assert_eq!(res.status(), 503);
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("The server is shutting down"), "{body}");
+
+        assert_eq!(actix_test::call_service(&app, upload()).await.status(), 200);
+    }
+}
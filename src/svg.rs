@@ -0,0 +1,318 @@
+use encoding_rs::Encoding;
+use quick_xml::{
+    events::{attributes::Attribute, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use serde::Deserialize;
+
+/// Elements that can run scripts or embed other documents.
+const FORBIDDEN_ELEMENTS: &[&str] = &[
+    "script",
+    "foreignobject",
+    "iframe",
+    "object",
+    "embed",
+    "handler",
+    "listener",
+];
+
+/// Elements that can change the value of other attributes.
+const ANIMATION_ELEMENTS: &[&str] = &["set", "animate", "animatemotion", "animatetransform"];
+
+/// SVGs are sanitized in memory, so larger ones are rejected regardless of the key's `max_size`.
+pub const MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// What to do with SVGs that could run scripts or load external resources.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SvgPolicy {
+    /// Remove the unsafe parts.
+    #[default]
+    Sanitize,
+    /// Reject the upload.
+    Reject,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SvgError {
+    #[error("Invalid SVG: {0}")]
+    Invalid(#[from] quick_xml::Error),
+    #[error("The SVG contains scripts or external references")]
+    Unsafe,
+}
+
+pub fn is_svg(extension: &str) -> bool {
+    mime_guess::from_ext(extension)
+        .first()
+        .is_some_and(|m| m.essence_str() == "image/svg+xml")
+}
+
+/// Removes scripts, event handlers, `foreignObject` and external references from an SVG.
+///
+/// `charset` is the encoding of `input`, the output is always UTF-8.
+pub fn sanitize(
+    input: &[u8],
+    charset: Option<&str>,
+    policy: SvgPolicy,
+) -> Result<Vec<u8>, SvgError> {
+    let encoding = charset
+        .and_then(|c| Encoding::for_label(c.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (input, _) = encoding.decode_with_bom_removal(input);
+
+    let mut reader = Reader::from_str(&input);
+    let mut writer = Writer::new(Vec::with_capacity(input.len()));
+    let mut removed = false;
+
+    loop {
+        let event = match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(e) if is_forbidden(&e)? => {
+                reader.read_to_end(e.name())?;
+                removed = true;
+                continue;
+            }
+            Event::Empty(e) if is_forbidden(&e)? => {
+                removed = true;
+                continue;
+            }
+            Event::Start(e) if e.local_name().as_ref().eq_ignore_ascii_case(b"style") => {
+                let end = e.to_end().into_owned();
+                let css = reader.read_text(end.name())?;
+                if has_external_url(&css) {
+                    removed = true;
+                    continue;
+                }
+                let start = clean_attributes(&e, &mut removed)?;
+                write(&mut writer, Event::Start(start))?;
+                write(&mut writer, Event::Text(BytesText::from_escaped(css)))?;
+                Event::End(end)
+            }
+            Event::Start(e) => Event::Start(clean_attributes(&e, &mut removed)?),
+            Event::Empty(e) => Event::Empty(clean_attributes(&e, &mut removed)?),
+            // Plain DOCTYPEs (like the ones exported by Inkscape or Illustrator) are harmless,
+            // but entity declarations and stylesheets aren't.
+            Event::DocType(e) => {
+                removed |= e.contains(&b'[') || e.windows(8).any(|w| w == b"<!ENTITY");
+                continue;
+            }
+            Event::PI(e) => {
+                removed |= e.target().eq_ignore_ascii_case(b"xml-stylesheet");
+                continue;
+            }
+            e => e,
+        };
+        write(&mut writer, event)?;
+    }
+
+    if removed && policy == SvgPolicy::Reject {
+        return Err(SvgError::Unsafe);
+    }
+    Ok(writer.into_inner())
+}
+
+fn write(writer: &mut Writer<Vec<u8>>, event: Event) -> Result<(), SvgError> {
+    writer
+        .write_event(event)
+        .map_err(|e| SvgError::Invalid(e.into()))
+}
+
+fn is_forbidden(e: &BytesStart) -> Result<bool, SvgError> {
+    let name = e.local_name();
+    let name = String::from_utf8_lossy(name.as_ref()).to_ascii_lowercase();
+    if FORBIDDEN_ELEMENTS.contains(&name.as_str()) {
+        return Ok(true);
+    }
+    if ANIMATION_ELEMENTS.contains(&name.as_str()) {
+        for attr in e.attributes() {
+            let attr = attr.map_err(quick_xml::Error::from)?;
+            if attr.key.local_name().as_ref() == b"attributeName" {
+                let target = attr.unescape_value()?.trim().to_ascii_lowercase();
+                if target.starts_with("on") || target.ends_with("href") {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn clean_attributes(e: &BytesStart, removed: &mut bool) -> Result<BytesStart<'static>, SvgError> {
+    let mut clean = e.to_owned();
+    clean.clear_attributes();
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        if is_safe_attribute(&attr) {
+            clean.push_attribute(attr);
+        } else {
+            *removed = true;
+        }
+    }
+    Ok(clean)
+}
+
+fn is_safe_attribute(attr: &Attribute) -> bool {
+    let name = attr.key.local_name();
+    let name = String::from_utf8_lossy(name.as_ref()).to_ascii_lowercase();
+    if name.starts_with("on") {
+        return false;
+    }
+    let Ok(value) = attr.unescape_value() else {
+        return false;
+    };
+    if matches!(name.as_str(), "href" | "src") {
+        return is_local_reference(&value);
+    }
+    !has_external_url(&value)
+}
+
+/// References to elements in the same document or embedded images.
+fn is_local_reference(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+    value.starts_with('#')
+        || (value.starts_with("data:image/") && !value.starts_with("data:image/svg"))
+}
+
+/// Checks for `url(...)` and `@import` in CSS and presentation attributes.
+///
+/// Escapes could hide both, so any backslash counts as external. Comments are removed first.
+fn has_external_url(css: &str) -> bool {
+    if css.contains('\\') {
+        return true;
+    }
+    let Some(css) = strip_comments(css) else {
+        return true;
+    };
+    let css = css.to_ascii_lowercase();
+    if css.contains("@import") {
+        return true;
+    }
+    css.match_indices("url(").any(|(idx, _)| {
+        let url = css[idx + 4..].trim_start().trim_start_matches(['"', '\'']);
+        !is_local_reference(url)
+    })
+}
+
+/// Removes CSS comments, `None` if one isn't closed.
+fn strip_comments(css: &str) -> Option<String> {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        let end = rest[start + 2..].find("*/")?;
+        rest = &rest[start + 2 + end + 2..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNSAFE: &[u8] = include_bytes!("../tests/corpus/unsafe.svg");
+
+    #[test]
+    fn removes_unsafe_parts() {
+        let out = sanitize(UNSAFE, Some("UTF-8"), SvgPolicy::Sanitize).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        for needle in [
+            "alert",
+            "evil.example",
+            "script",
+            "foreignObject",
+            "ENTITY",
+            "xml-stylesheet",
+            "@import",
+            "javascript",
+        ] {
+            assert!(!out.contains(needle), "{needle} in {out}");
+        }
+        for needle in [
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"fill="url(#g)""#,
+            r##"<use href="#g"/>"##,
+            "<text x=\"1\" y=\"5\">click</text>",
+            "<style>rect { stroke: #fff; }</style>",
+        ] {
+            assert!(out.contains(needle), "{needle} not in {out}");
+        }
+    }
+
+    #[test]
+    fn detects_obfuscated_urls() {
+        for css in [
+            "fill: url(https://evil.example/a)",
+            "fill: URL( 'https://evil.example/a')",
+            r"fill: \75rl(https://evil.example/a)",
+            r"fill: u\rl(https://evil.example/a)",
+            "fill: url/**/(https://evil.example/a)",
+            "@im/* */port 'https://evil.example/a.css'",
+            "fill: url(#g) /* unclosed",
+        ] {
+            assert!(has_external_url(css), "{css}");
+        }
+        for css in ["fill: url(#g)", "/* a comment */ stroke: #fff", "fill: red"] {
+            assert!(!has_external_url(css), "{css}");
+        }
+    }
+
+    #[test]
+    fn removes_obfuscated_references() {
+        let svg = br#"<svg><a><set attributeName=" href" to="javascript:alert(1)"/></a></svg>"#;
+        let out = sanitize(svg, None, SvgPolicy::Sanitize).unwrap();
+        assert_eq!(out, b"<svg><a></a></svg>");
+
+        let svg = br#"<svg><style>rect { fill: u\rl(https://evil.example/a) }</style></svg>"#;
+        assert!(matches!(
+            sanitize(svg, None, SvgPolicy::Reject),
+            Err(SvgError::Unsafe)
+        ));
+    }
+
+    #[test]
+    fn keeps_safe_svgs() {
+        let svg = include_bytes!("../tests/corpus/image.svg");
+        assert_eq!(
+            sanitize(svg, None, SvgPolicy::Reject).unwrap(),
+            svg.as_slice()
+        );
+    }
+
+    #[test]
+    fn rejects_unsafe_svgs() {
+        assert!(matches!(
+            sanitize(UNSAFE, None, SvgPolicy::Reject),
+            Err(SvgError::Unsafe)
+        ));
+        assert!(matches!(
+            sanitize(b"<svg><rect></svg>", None, SvgPolicy::Sanitize),
+            Err(SvgError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_plain_doctypes() {
+        let svg = br#"<?xml version="1.0"?>
+<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd">
+<svg><rect/></svg>"#;
+        let out = sanitize(svg, None, SvgPolicy::Reject).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("DOCTYPE"));
+
+        let svg = br#"<!DOCTYPE svg [<!ENTITY x "y">]><svg>&x;</svg>"#;
+        assert!(matches!(
+            sanitize(svg, None, SvgPolicy::Reject),
+            Err(SvgError::Unsafe)
+        ));
+    }
+
+    #[test]
+    fn decodes_charset() {
+        let mut svg = vec![0xff, 0xfe];
+        for unit in "<svg><text>Grüße</text></svg>".encode_utf16() {
+            svg.extend_from_slice(&unit.to_le_bytes());
+        }
+        let out = sanitize(&svg, Some("UTF-16LE"), SvgPolicy::Reject).unwrap();
+        assert_eq!(out, "<svg><text>Grüße</text></svg>".as_bytes());
+    }
+}
@@ -1,6 +1,8 @@
-use actix_files::NamedFile;
-use actix_web::{get, http::header, web::Path, HttpResponse, Responder};
+use actix_web::{get, http::header, web::Path, HttpResponse};
 use askama::Template;
+use tracing::warn;
+
+use crate::{meta, raw};
 
 #[derive(askama::Template)]
 #[template(path = "audio.html")]
@@ -8,6 +10,20 @@ struct AudioTemplate<'a> {
     file: &'a str,
 }
 
+#[derive(askama::Template)]
+#[template(path = "text.html")]
+struct TextTemplate<'a> {
+    file: &'a str,
+}
+
+#[derive(askama::Template)]
+#[template(path = "pdf.html")]
+struct PdfTemplate<'a> {
+    file: &'a str,
+    title: Option<&'a str>,
+    description: Option<String>,
+}
+
 #[derive(Debug, thiserror::Error, actix_web_error::Json)]
 #[error("Couldn't render: {0}")]
 #[status(500)]
@@ -16,7 +32,7 @@ pub struct TemplateError(#[from] askama::Error);
 #[get("/a/{name}")]
 pub async fn audio_template(path: Path<String>) -> Result<HttpResponse, TemplateError> {
     let rendered = AudioTemplate {
-        file: &format!("/{path}"),
+        file: &raw::file_src(&path),
     }
     .render()?;
     Ok(HttpResponse::Ok()
@@ -26,6 +42,37 @@ pub async fn audio_template(path: Path<String>) -> Result<HttpResponse, Template
 }
 
 #[get("/t/{name}")]
-pub async fn text_template() -> impl Responder {
-    NamedFile::open_async("./static/pages/text/text.html").await
+pub async fn text_template(path: Path<String>) -> Result<HttpResponse, TemplateError> {
+    let rendered = TextTemplate {
+        file: &raw::file_src(&path),
+    }
+    .render()?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((header::CACHE_CONTROL, "max-age=3600"))
+        .body(rendered))
+}
+
+#[get("/p/{name}")]
+pub async fn pdf_template(path: Path<String>) -> Result<HttpResponse, TemplateError> {
+    let meta = match meta::read(&path).await {
+        Ok(m) => m.and_then(|m| m.pdf),
+        Err(e) => {
+            warn!(error = ?e, file = %path, "Couldn't read metadata");
+            None
+        }
+    };
+    let rendered = PdfTemplate {
+        file: &raw::file_src(&path),
+        title: meta.as_ref().and_then(|m| m.title.as_deref()),
+        description: meta.as_ref().map(|m| match m.pages {
+            1 => "1 page".to_owned(),
+            n => format!("{n} pages"),
+        }),
+    }
+    .render()?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((header::CACHE_CONTROL, "max-age=3600"))
+        .body(rendered))
 }
@@ -0,0 +1,205 @@
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{
+    http::{header, uri::Authority},
+    HttpRequest, HttpResponse,
+};
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{config, listen::Listener};
+
+/// How often the certificate and key are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The `[tls]` section of the config, `bind` serves HTTPS if it's present.
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain.
+    pub cert: PathBuf,
+    /// PEM file with the private key.
+    pub key: PathBuf,
+    /// An address that redirects HTTP requests to HTTPS (e.g. `"0.0.0.0:80"`).
+    pub redirect: Option<String>,
+}
+
+/// Hands out the current certificate, which is swapped when the files change.
+#[derive(Debug)]
+struct Resolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// Reads the certificate chain and key and checks that they belong together.
+pub fn load(config: &TlsConfig) -> io::Result<CertifiedKey> {
+    let pem_error =
+        |path: &Path, e| io::Error::other(format!("Couldn't read {}: {e}", path.display()));
+    let certs = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| pem_error(&config.cert, e))?;
+    if certs.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} doesn't contain a certificate",
+            config.cert.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key).map_err(|e| pem_error(&config.key, e))?;
+    let key = ring::sign::any_supported_type(&key).map_err(io::Error::other)?;
+    let certified = CertifiedKey::new(certs, key);
+    certified
+        .keys_match()
+        .map_err(|e| io::Error::other(format!("The certificate doesn't match the key: {e}")))?;
+    Ok(certified)
+}
+
+/// The config for `bind` and a task reloading the certificate when its files change.
+pub fn server_config(
+    config: &TlsConfig,
+) -> io::Result<(ServerConfig, impl Future<Output = ()> + use<>)> {
+    let resolver = Arc::new(Resolver(RwLock::new(Arc::new(load(config)?))));
+    let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    Ok((server_config, watch(resolver)))
+}
+
+async fn watch(resolver: Arc<Resolver>) {
+    let files_modified = || {
+        let config = config::get();
+        let tls = config.tls.as_ref()?;
+        Some((modified(&tls.cert), modified(&tls.key)))
+    };
+    let mut last_modified = files_modified();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now_modified = files_modified();
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+
+        let config = config::get();
+        let Some(tls) = &config.tls else {
+            continue;
+        };
+        match load(tls) {
+            Ok(certified) => {
+                *resolver.0.write().unwrap() = Arc::new(certified);
+                info!("Reloaded TLS certificate");
+            }
+            // The certificate and key might not be written at the same time.
+            Err(e) => warn!("Keeping the current TLS certificate: {e}"),
+        }
+    }
+}
+
+/// Redirects to the same URL on HTTPS.
+pub async fn redirect(req: HttpRequest) -> HttpResponse {
+    let config = config::get();
+    let port = config
+        .bind
+        .iter()
+        .find_map(Listener::port)
+        .filter(|p| *p != 443);
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<Authority>().ok());
+    let location = match host {
+        Some(host) => {
+            let port = port.map(|p| format!(":{p}")).unwrap_or_default();
+            format!("https://{}{port}{}", host.host(), req.uri())
+        }
+        None => format!("{}{}", config.domain, req.uri()),
+    };
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn tls(cert: &str, key: &str) -> TlsConfig {
+        TlsConfig {
+            cert: Path::new("tests/tls").join(cert),
+            key: Path::new("tests/tls").join(key),
+            redirect: None,
+        }
+    }
+
+    #[test]
+    fn loads_matching_keys() {
+        assert!(load(&tls("cert.pem", "key.pem")).is_ok());
+
+        let err = load(&tls("cert.pem", "other-key.pem")).unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err}");
+        // The key isn't a certificate.
+        let err = load(&tls("key.pem", "key.pem")).unwrap_err();
+        assert!(err.to_string().contains("doesn't contain"), "{err}");
+        assert!(load(&tls("missing.pem", "key.pem")).is_err());
+    }
+
+    async fn location(host: Option<&str>, uri: &str) -> String {
+        let mut req = TestRequest::get().uri(uri);
+        if let Some(host) = host {
+            req = req.insert_header((header::HOST, host));
+        }
+        let res = redirect(req.to_http_request()).await;
+        assert_eq!(res.status().as_u16(), 308);
+        let location = res.headers().get(header::LOCATION).unwrap();
+        location.to_str().unwrap().to_owned()
+    }
+
+    #[actix_web::test]
+    async fn redirects_to_https() {
+        // `bind` of the test config uses the port 4833.
+        config::use_test_config();
+        assert_eq!(
+            location(Some("example.com"), "/a.png?x=1").await,
+            "https://example.com:4833/a.png?x=1"
+        );
+        // The port of the HTTP request is replaced.
+        assert_eq!(
+            location(Some("example.com:80"), "/").await,
+            "https://example.com:4833/"
+        );
+        assert_eq!(
+            location(Some("[::1]:80"), "/a").await,
+            "https://[::1]:4833/a"
+        );
+        // Without a valid host, `domain` is used.
+        assert_eq!(location(None, "/a").await, "http://localhost:4833/a");
+        assert_eq!(
+            location(Some("not a host"), "/a").await,
+            "http://localhost:4833/a"
+        );
+    }
+}
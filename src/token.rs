@@ -0,0 +1,149 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Limits, Scope},
+    secrets,
+};
+
+/// Distinguishes tokens from the configured keys.
+pub const PREFIX: &str = "upl.";
+
+/// Deletion keys are signed with the same secrets.
+const TOKEN_DOMAIN: &[u8] = b"token:";
+
+/// The contents of a token.
+///
+/// Tokens are `upl.{claims}.{signature}` where the claims are base64 encoded JSON
+/// and the signature is an HMAC of the encoded claims with one of the server's secrets.
+/// They're verified without a lookup, so they can only be invalidated by retiring the secret.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// Uploads are attributed to this name.
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Unix timestamp (seconds) after which the token is invalid.
+    pub exp: u64,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("The token is malformed")]
+    Malformed,
+    #[error("The token's signature is invalid")]
+    BadSignature,
+    #[error("The token has expired")]
+    Expired,
+    #[error("The token's name belongs to a key")]
+    KeyName,
+}
+
+pub fn issue(name: String, scopes: Vec<Scope>, valid_for: Duration, limits: Limits) -> String {
+    let claims = Claims {
+        name,
+        scopes,
+        exp: (SystemTime::now() + valid_for)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        limits,
+    };
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+    let signature = URL_SAFE_NO_PAD.encode(secrets::sign(&[TOKEN_DOMAIN, payload.as_bytes()]));
+    format!("{PREFIX}{payload}.{signature}")
+}
+
+pub fn verify(token: &str) -> Result<Claims, TokenError> {
+    let (payload, signature) = token
+        .strip_prefix(PREFIX)
+        .and_then(|t| t.split_once('.'))
+        .ok_or(TokenError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| TokenError::Malformed)?;
+    if !secrets::verify(&[TOKEN_DOMAIN, payload.as_bytes()], &signature) {
+        return Err(TokenError::BadSignature);
+    }
+
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| TokenError::Malformed)?;
+    let claims: Claims = serde_json::from_slice(&payload).map_err(|_| TokenError::Malformed)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if claims.exp <= now {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn issue_for(valid_for: Duration) -> String {
+        config::use_test_config();
+        issue(
+            "contractor".to_owned(),
+            vec![Scope::Upload],
+            valid_for,
+            Limits::default(),
+        )
+    }
+
+    #[test]
+    fn accepts_issued() {
+        let claims = verify(&issue_for(Duration::from_secs(60))).unwrap();
+        assert_eq!(claims.name, "contractor");
+        assert_eq!(claims.scopes, [Scope::Upload]);
+    }
+
+    #[test]
+    fn rejects_modified_claims() {
+        let token = issue_for(Duration::from_secs(60));
+        let (payload, signature) = token.strip_prefix(PREFIX).unwrap().split_once('.').unwrap();
+        let claims = String::from_utf8(URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        let claims = claims.replace("\"upload\"", "\"admin\"");
+        let modified = format!("{PREFIX}{}.{signature}", URL_SAFE_NO_PAD.encode(claims));
+        assert!(matches!(verify(&modified), Err(TokenError::BadSignature)));
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let token = issue_for(Duration::from_secs(60));
+        let (payload, _) = token.split_once('.').unwrap().1.split_once('.').unwrap();
+        let signature = URL_SAFE_NO_PAD.encode([0; 29]);
+        let forged = format!("{PREFIX}{payload}.{signature}");
+        assert!(matches!(verify(&forged), Err(TokenError::BadSignature)));
+    }
+
+    #[test]
+    fn rejects_expired() {
+        let token = issue_for(Duration::ZERO);
+        assert!(matches!(verify(&token), Err(TokenError::Expired)));
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        config::use_test_config();
+        for token in ["", "Bearer abc", "upl.", "upl.abc", "upl.abc.!!!"] {
+            assert!(
+                matches!(verify(token), Err(TokenError::Malformed)),
+                "{token:?}"
+            );
+        }
+        // Signed, but not claims.
+        let payload = URL_SAFE_NO_PAD.encode("{}");
+        let signature = URL_SAFE_NO_PAD.encode(secrets::sign(&[TOKEN_DOMAIN, payload.as_bytes()]));
+        let token = format!("{PREFIX}{payload}.{signature}");
+        assert!(matches!(verify(&token), Err(TokenError::Malformed)));
+    }
+}
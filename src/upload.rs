@@ -1,23 +1,39 @@
 use std::{
     fmt, io,
-    path::Path,
+    net::IpAddr,
+    path::{Path, PathBuf},
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 
 use actix_web::{
     error::PayloadError,
     http::header::ContentType,
-    web::{Header, Payload},
+    web::{Header, Payload, ReqData},
     HttpRequest, HttpResponse,
 };
 use bytes::{Bytes, BytesMut};
 use futures::{Stream, StreamExt};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tokio::io::AsyncWriteExt;
-use tracing::warn;
+use tracing::{info, warn};
 
-use crate::{config::CONFIG, deletion, rng};
+use crate::{
+    audit::{self, Event, Record},
+    auth::Identity,
+    config::{self, Config},
+    deletion,
+    detect::{self, Detected, TypeHint},
+    meta::{self, FileMeta, PdfMeta},
+    metrics,
+    ratelimit::ClientIp,
+    rng,
+    shutdown::RunningUpload,
+    svg,
+    webhooks::{self, WebhookEvent},
+};
 
 // <=> Used when uploading from the homepage.
 const FILENAME_POST_HEADER: &str = "X-Upload-Filename";
@@ -31,14 +47,14 @@ pub enum MultipartError {
     #[status(400)]
     Multer(multer::Error),
     #[error("Upload error: {0}")]
-    #[status(500)]
+    #[status(transparent)]
     Upload(UploadError<multer::Error>),
 }
 
 #[derive(Debug, thiserror::Error, actix_web_error::Json)]
 pub enum PostError {
     #[error("Upload error: {0}")]
-    #[status(500)]
+    #[status(transparent)]
     Upload(UploadError<PayloadError>),
 }
 
@@ -49,6 +65,32 @@ pub enum UploadError<E: fmt::Display + fmt::Debug> {
     Io(io::Error),
     #[error("{0}")]
     Inner(E),
+    #[error("{0}")]
+    #[status(415)]
+    Mismatch(detect::Mismatch),
+    #[error("{0}")]
+    #[status(400)]
+    Svg(svg::SvgError),
+    #[error("Files of type '{0}' aren't allowed")]
+    #[status(415)]
+    TypeNotAllowed(String),
+    #[error("The file is larger than {0} bytes")]
+    #[status(413)]
+    TooLarge(u64),
+}
+
+impl<E: fmt::Display + fmt::Debug> UploadError<E> {
+    /// The name of the variant for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Inner(_) => "inner",
+            Self::Mismatch(_) => "mismatch",
+            Self::Svg(_) => "svg",
+            Self::TypeNotAllowed(_) => "type_not_allowed",
+            Self::TooLarge(_) => "too_large",
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -57,16 +99,11 @@ pub struct UploadResponse {
     deletion_link: String,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-enum TypeHint {
-    None,
-    Audio,
-    Text,
-}
-
 pub async fn upload_multipart(
     body: Payload,
     ct: Header<ContentType>,
+    identity: ReqData<Identity>,
+    ClientIp(ip): ClientIp,
 ) -> Result<HttpResponse, MultipartError> {
     let boundary = multer::parse_boundary(ct.as_ref()).map_err(MultipartError::Multer)?;
     let mut mp = multer::Multipart::new(UnsafePayload(body), boundary);
@@ -74,9 +111,16 @@ pub async fn upload_multipart(
         Some(mut field) => {
             let filename = field.file_name().map(|f| f.to_owned());
             let ct = field.content_type().cloned();
-            inner_upload(&mut field, ct.as_ref(), filename.as_ref().map(Path::new))
-                .await
-                .map_err(MultipartError::Upload)
+            inner_upload(
+                &mut field,
+                ct.as_ref(),
+                filename.as_ref().map(Path::new),
+                &identity,
+                ip,
+            )
+            .await
+            .inspect_err(|e| failed(&identity, ip, e.kind()))
+            .map_err(MultipartError::Upload)
         }
         None => Err(MultipartError::NoEntry),
     }
@@ -86,6 +130,8 @@ pub async fn upload_post(
     mut body: Payload,
     h: Option<Header<ContentType>>,
     req: HttpRequest,
+    identity: ReqData<Identity>,
+    ClientIp(ip): ClientIp,
 ) -> Result<HttpResponse, PostError> {
     let mime = h.map(|h| h.0 .0);
     let filename = req
@@ -93,171 +139,237 @@ pub async fn upload_post(
         .get(FILENAME_POST_HEADER)
         .and_then(|h| h.to_str().ok())
         .map(Path::new);
-    inner_upload(&mut body, mime.as_ref(), filename)
+    inner_upload(&mut body, mime.as_ref(), filename, &identity, ip)
         .await
+        .inspect_err(|e| failed(&identity, ip, e.kind()))
         .map_err(PostError::Upload)
 }
 
+/// Counts and audits a failed upload.
+fn failed(identity: &Identity, ip: Option<IpAddr>, kind: &'static str) {
+    metrics::upload_error(kind);
+    audit::log(Record {
+        key: Some(identity.name.clone()),
+        ip,
+        ..Record::new(Event::Upload, kind)
+    });
+}
+
 async fn inner_upload<S, E>(
     stream: &mut S,
     content_type: Option<&mime::Mime>,
     upload_filename: Option<&Path>,
+    identity: &Identity,
+    ip: Option<IpAddr>,
 ) -> Result<HttpResponse, UploadError<E>>
 where
     S: Stream<Item = Result<Bytes, E>> + Unpin,
     E: fmt::Debug + fmt::Display,
 {
+    let start = Instant::now();
+    let _running = RunningUpload::start();
+    let config = config::get();
+    let max_size = identity.limits.max_size.unwrap_or(u64::MAX);
+    let initial_buf = detect::sniff(stream).await.map_err(UploadError::Inner)?;
+    if initial_buf.is_empty() {
+        return Err(UploadError::Io(io::Error::from(
+            io::ErrorKind::UnexpectedEof,
+        )));
+    }
+    let Detected {
+        extension,
+        hint,
+        charset,
+        mismatch,
+    } = detect::detect(
+        &initial_buf,
+        content_type,
+        upload_filename,
+        config.type_mismatch,
+    )
+    .map_err(UploadError::Mismatch)?;
+    if let Some(mismatch) = &mismatch {
+        warn!(
+            key = identity.name,
+            claimed = mismatch.claimed,
+            detected = mismatch.detected,
+            policy = config.type_mismatch.as_str(),
+            "The content doesn't match the specified type"
+        );
+        metrics::type_mismatch(config.type_mismatch);
+    }
+    if !identity.limits.allows_type(&extension) {
+        return Err(UploadError::TypeNotAllowed(extension.into_owned()));
+    }
+
+    // SVGs need to be sanitized as a whole before they're stored.
+    let (initial_buf, charset) = if svg::is_svg(&extension) {
+        let max_size = max_size.min(svg::MAX_SIZE);
+        let mut buf = BytesMut::from(initial_buf);
+        while let Some(item) = stream.next().await {
+            let item = item.map_err(UploadError::Inner)?;
+            if (buf.len() + item.len()) as u64 > max_size {
+                return Err(UploadError::TooLarge(max_size));
+            }
+            buf.extend_from_slice(&item);
+        }
+        let policy = config.svg;
+        let sanitized = tokio::task::spawn_blocking(move || svg::sanitize(&buf, charset, policy))
+            .await
+            .map_err(|e| UploadError::Io(e.into()))?
+            .map_err(UploadError::Svg)?;
+        (Bytes::from(sanitized), Some(encoding_rs::UTF_8.name()))
+    } else {
+        (initial_buf, charset)
+    };
+
     let mut filename = rng::generate_name();
     filename.push('.');
-    let (extension, ty, initial_buf) = determine_extension(stream, content_type).await?;
-    // Check if the user provided a filename, and try to use its extension.
-    // However, we still check the expected extension, because we want to determine the filetype,
-    // so we can return an enhanced page.
-    if let Some(f) = upload_filename
-        .and_then(|p| p.extension())
-        .and_then(|s| s.to_str())
-    {
-        // overwritten extension
-        filename.push_str(f);
-    } else {
-        // determined extension
-        filename.push_str(extension);
-    }
+    filename.push_str(&extension);
 
-    let file_path = CONFIG.file_dir.join(&filename);
-    let res = async move /* try */ {
-        let mut file = tokio::fs::File::create(file_path)
+    let file_path = config.file_dir.join(&filename);
+    let mut partial = PartialUpload {
+        path: &file_path,
+        identity,
+        ip,
+        done: false,
+    };
+    let res = async /* try */ {
+        let mut size = initial_buf.len() as u64;
+        if size > max_size {
+            return Err(UploadError::TooLarge(max_size));
+        }
+        let mut file = tokio::fs::File::create(&file_path)
             .await
             .map_err(UploadError::Io)?;
 
-        if let Some(buf) = initial_buf {
-            file.write_all(&buf).await.map_err(UploadError::Io)?;
-        }
+        let mut hasher = Sha256::new();
+        hasher.update(&initial_buf);
+        file.write_all(&initial_buf)
+            .await
+            .map_err(UploadError::Io)?;
 
         while let Some(item) = stream.next().await {
             let item = item.map_err(UploadError::Inner)?;
+            size += item.len() as u64;
+            if size > max_size {
+                return Err(UploadError::TooLarge(max_size));
+            }
+            hasher.update(&item);
             file.write_all(&item).await.map_err(UploadError::Io)?;
         }
 
-        Ok(())
+        Ok((size, hex::encode(hasher.finalize())))
     }
     .await;
+    partial.done = true;
 
-    match res {
-        Ok(_) => Ok(HttpResponse::Ok().json(UploadResponse {
-            link: match ty {
-                TypeHint::None => format!("{}/{filename}", CONFIG.domain),
-                TypeHint::Audio => format!("{}/a/{filename}", CONFIG.domain),
-                TypeHint::Text => format!("{}/t/{filename}", CONFIG.domain),
+    if let Ok((_, hash)) = &res {
+        let meta = FileMeta {
+            pdf: match hint {
+                TypeHint::Pdf => read_pdf_meta(file_path.clone()).await,
+                _ => None,
             },
-            deletion_link: format!(
-                "{}/d/{filename}/{}",
-                CONFIG.domain,
-                deletion::make_key(&filename)
-            ),
-        })),
-        Err(e) => {
-            warn!(error = ?e, "Couldn't upload");
-            Err(e)
+            charset: charset.map(str::to_owned),
+            uploader: Some(identity.name.clone()),
+            sha256: Some(hash.clone()),
+            ..Default::default()
+        };
+        if let Err(e) = meta::write(&filename, &meta).await {
+            warn!(error = ?e, "Couldn't write metadata");
         }
     }
-}
 
-async fn determine_extension<S, E>(
-    stream: &mut S,
-    content_type: Option<&mime::Mime>,
-) -> Result<(&'static str, TypeHint, Option<Bytes>), UploadError<E>>
-where
-    S: Stream<Item = Result<Bytes, E>> + Unpin,
-    E: fmt::Debug + fmt::Display,
-{
-    if let Some((e, ty)) = content_type.and_then(extension_from_mime) {
-        return Ok((e, ty, None));
-    }
-    let mut bytes = None; // this doesn't allocate
-    let maybe = loop {
-        match stream.next().await {
-            Some(Ok(item)) => {
-                match bytes {
-                    None => {
-                        // this should be more common
-                        match infer::get(&item) {
-                            Some(ty) => {
-                                return Ok((ty.extension(), type_hint_from_infer(&ty), Some(item)));
-                            }
-                            None => {
-                                bytes = Some(BytesMut::from(item.as_ref()));
-                            }
-                        }
-                    }
-                    Some(ref mut buf) => {
-                        buf.extend_from_slice(&item);
-                        match infer::get(buf) {
-                            Some(ty) => break Some((ty.extension(), type_hint_from_infer(&ty))),
-                            None if buf.len() > 256 => {
-                                break None;
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-            }
-            Some(Err(e)) => {
-                // discard everything
-                return Err(UploadError::Inner(e));
-            }
-            None => {
-                // until now we couldn't determine the type
-                break None;
-            }
+    match res {
+        Ok((size, hash)) => {
+            metrics::upload(hint, &identity.name, size, start.elapsed());
+            info!(key = identity.name, file = filename, "Uploaded");
+            audit::log(Record {
+                key: Some(identity.name.clone()),
+                ip,
+                file: Some(filename.clone()),
+                size: Some(size),
+                hash: Some(hash.clone()),
+                claimed_type: mismatch.as_ref().map(|m| m.claimed.clone()),
+                detected_type: mismatch.as_ref().map(|m| m.detected.to_owned()),
+                ..Record::new(Event::Upload, "ok")
+            });
+            let link = link(&config, &filename, hint);
+            webhooks::send(webhooks::Event {
+                key: Some(identity.name.clone()),
+                size: Some(size),
+                hash: Some(hash),
+                link: Some(link.clone()),
+                ..webhooks::Event::new(WebhookEvent::Upload, filename.clone())
+            })
+            .await;
+            Ok(HttpResponse::Ok().json(UploadResponse {
+                link,
+                deletion_link: deletion::link(&config, &filename, 0),
+            }))
         }
-    };
-
-    match maybe {
-        Some((ty, hint)) => Ok((ty, hint, bytes.map(BytesMut::freeze))),
-        None => match bytes {
-            Some(bytes) => {
-                if std::str::from_utf8(&bytes).is_ok() {
-                    Ok(("txt", TypeHint::Text, Some(bytes.freeze())))
-                } else {
-                    Ok(("bin", TypeHint::None, Some(bytes.freeze())))
+        Err(e) => {
+            warn!(key = identity.name, error = ?e, "Couldn't upload");
+            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(error = ?e, "Couldn't remove incomplete upload");
                 }
             }
-            None => Err(UploadError::Io(io::Error::from(
-                io::ErrorKind::UnexpectedEof,
-            ))),
-        },
+            Err(e)
+        }
     }
 }
 
-fn extension_from_mime(mime: &mime::Mime) -> Option<(&'static str, TypeHint)> {
-    (mime.type_().as_str() == "*" || mime.subtype().as_str() == "*")
-        .then_some(mime)
-        .and_then(|mime| mime_guess::get_extensions(mime.type_().as_str(), mime.subtype().as_str()))
-        .filter(
-            |ext| !ext.is_empty() && ext.len() > 10, // basic heuristic for too many extensions
-        )
-        .and_then(|ext| ext.last().copied())
-        .map(|e| (e, type_hint_from_mime(mime.type_().as_str())))
+/// Removes the file of an upload that was cancelled while it was written
+/// (e.g. because it didn't finish before the server stopped).
+struct PartialUpload<'a> {
+    path: &'a Path,
+    identity: &'a Identity,
+    ip: Option<IpAddr>,
+    done: bool,
 }
 
-fn type_hint_from_mime(ty: &str) -> TypeHint {
-    if ty == mime::AUDIO.as_str() {
-        TypeHint::Audio
-    } else if ty == mime::TEXT.as_str() {
-        TypeHint::Text
-    } else {
-        TypeHint::None
+impl Drop for PartialUpload<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        warn!(key = self.identity.name, "Upload was cancelled");
+        if let Err(e) = std::fs::remove_file(self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!(error = ?e, "Couldn't remove incomplete upload");
+            }
+        }
+        failed(self.identity, self.ip, "cancelled");
     }
 }
 
-fn type_hint_from_infer(inf: &infer::Type) -> TypeHint {
-    if inf.mime_type().starts_with(mime::AUDIO.as_str()) {
-        TypeHint::Audio
-    } else if inf.mime_type().starts_with(mime::TEXT.as_str()) {
-        TypeHint::Text
-    } else {
-        TypeHint::None
+/// The link to the view of a file.
+pub fn link(config: &Config, filename: &str, hint: TypeHint) -> String {
+    match hint {
+        TypeHint::None => format!("{}/{filename}", config.raw_domain()),
+        TypeHint::Audio => format!("{}/a/{filename}", config.domain),
+        TypeHint::Text => format!("{}/t/{filename}", config.domain),
+        TypeHint::Pdf => format!("{}/p/{filename}", config.domain),
+    }
+}
+
+/// Extracts the title and page count of an uploaded PDF for the embeds of the PDF view.
+async fn read_pdf_meta(path: PathBuf) -> Option<PdfMeta> {
+    let res = tokio::task::spawn_blocking(move || lopdf::Document::load_metadata(path)).await;
+    match res {
+        Ok(Ok(pdf)) => Some(PdfMeta {
+            title: pdf.title.filter(|t| !t.trim().is_empty()),
+            pages: pdf.page_count,
+        }),
+        Ok(Err(e)) => {
+            warn!(error = ?e, "Couldn't read PDF metadata");
+            None
+        }
+        Err(e) => {
+            warn!(error = ?e, "Couldn't join PDF task");
+            None
+        }
     }
 }
 
@@ -0,0 +1,386 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, KeyInit, Mac};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::{config, rng};
+
+/// Deliveries are dropped after this many failed attempts.
+const MAX_ATTEMPTS: u32 = 10;
+/// How often the queue is checked for deliveries added by other processes (like `clean`).
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static QUEUED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// An endpoint that receives events as JSON.
+#[derive(Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Used to sign the body with HMAC-SHA256 (`X-Uploader-Signature: sha256=<hex>`).
+    pub secret: String,
+    #[serde(default = "WebhookEvent::all")]
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Upload,
+    /// A deletion with a deletion link or through the admin API.
+    Deletion,
+    /// A removal by the `clean` subcommand.
+    Expiry,
+}
+
+impl WebhookEvent {
+    pub fn all() -> Vec<Self> {
+        vec![Self::Upload, Self::Deletion, Self::Expiry]
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Deletion => "deletion",
+            Self::Expiry => "expiry",
+        }
+    }
+}
+
+/// The body sent to the webhooks.
+#[derive(Serialize)]
+pub struct Event {
+    pub event: WebhookEvent,
+    /// RFC 3339.
+    pub time: String,
+    pub file: String,
+    /// The key that uploaded or deleted the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The hex SHA-256 of the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The link to the view of uploads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl Event {
+    pub fn new(event: WebhookEvent, file: String) -> Self {
+        Self {
+            event,
+            time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            file,
+            key: None,
+            size: None,
+            hash: None,
+            link: None,
+        }
+    }
+}
+
+/// A pending delivery, stored in the queue directory until it succeeds.
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    event: WebhookEvent,
+    body: String,
+    attempts: u32,
+    /// Unix timestamp in seconds.
+    next_attempt: u64,
+}
+
+pub fn dir() -> PathBuf {
+    config::get().file_dir.join(".webhooks")
+}
+
+/// The deliveries of an event as queue files.
+fn deliveries(event: &Event) -> Vec<(PathBuf, Vec<u8>)> {
+    let body = match serde_json::to_string(event) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(error = ?e, "Couldn't serialize webhook event");
+            return Vec::new();
+        }
+    };
+    let now = now();
+    config::get()
+        .webhooks
+        .iter()
+        .filter(|w| w.events.contains(&event.event))
+        .filter_map(|w| {
+            let delivery = Delivery {
+                url: w.url.clone(),
+                event: event.event,
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: now,
+            };
+            // The name sorts by time, so deliveries are attempted in order.
+            let name = format!("{now:012}-{}.json", rng::generate_name());
+            serde_json::to_vec(&delivery)
+                .ok()
+                .map(|data| (dir().join(name), data))
+        })
+        .collect()
+}
+
+/// Queues the event for all webhooks subscribed to it.
+pub async fn send(event: Event) {
+    for (path, data) in deliveries(&event) {
+        if let Err(e) = write_delivery(&path, &data).await {
+            warn!(error = ?e, "Couldn't queue webhook delivery");
+        }
+    }
+    QUEUED.notify_one();
+}
+
+/// Blocking version of [`send`] for use in subcommands, the server delivers the events.
+pub fn send_sync(event: Event) {
+    if config::get().webhooks.is_empty() {
+        return;
+    }
+    if let Err(e) = std::fs::create_dir_all(dir()) {
+        eprintln!("Failed to create the webhook queue: {e}");
+        return;
+    }
+    for (path, data) in deliveries(&event) {
+        let temp = temp_path(&path);
+        if let Err(e) = std::fs::write(&temp, data).and_then(|_| std::fs::rename(&temp, &path)) {
+            eprintln!("Failed to queue webhook delivery: {e}");
+        }
+    }
+}
+
+/// Queue files are written under a hidden name and then renamed,
+/// so they're never read while they're incomplete.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.tmp"))
+}
+
+async fn write_delivery(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    tokio::fs::write(&temp, data).await?;
+    tokio::fs::rename(&temp, path).await
+}
+
+/// The queued deliveries, oldest first.
+async fn queued(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        // Deliveries that are still being written.
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        files.push(entry.path());
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Removes a finished delivery, a failure only affects this one.
+async fn remove_delivery(path: &Path) {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            warn!(error = ?e, path = %path.display(), "Couldn't remove webhook delivery");
+        }
+        _ => (),
+    }
+}
+
+/// Delivers queued events until the server stops.
+pub async fn run() {
+    let client = awc::Client::builder().timeout(TIMEOUT).finish();
+    loop {
+        let next = match process(&client).await {
+            Ok(next) => next,
+            Err(e) => {
+                warn!(error = ?e, "Couldn't read webhook queue");
+                None
+            }
+        };
+        let wait = next
+            .map(|next| Duration::from_secs(next.saturating_sub(now())))
+            .map_or(POLL_INTERVAL, |d| d.min(POLL_INTERVAL));
+        let _ = tokio::time::timeout(wait, QUEUED.notified()).await;
+    }
+}
+
+/// Attempts all due deliveries and returns when the next one is due.
+async fn process(client: &awc::Client) -> io::Result<Option<u64>> {
+    let files = queued(&dir()).await?;
+    let mut next = None::<u64>;
+    for path in files {
+        let mut delivery = match tokio::fs::read(&path)
+            .await
+            .map(|d| serde_json::from_slice::<Delivery>(&d))
+        {
+            Ok(Ok(delivery)) => delivery,
+            Ok(Err(e)) => {
+                warn!(error = ?e, path = %path.display(), "Dropping invalid webhook delivery");
+                remove_delivery(&path).await;
+                continue;
+            }
+            // It might have been delivered already.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if delivery.next_attempt > now() {
+            next = Some(next.map_or(delivery.next_attempt, |n| n.min(delivery.next_attempt)));
+            continue;
+        }
+        let config = config::get();
+        let Some(webhook) = config.webhooks.iter().find(|w| w.url == delivery.url) else {
+            warn!(url = delivery.url, "Dropping delivery to a removed webhook");
+            remove_delivery(&path).await;
+            continue;
+        };
+
+        match deliver(client, webhook, &delivery).await {
+            Ok(()) => {
+                debug!(url = delivery.url, "Delivered webhook");
+                remove_delivery(&path).await;
+            }
+            Err(e) => {
+                delivery.attempts += 1;
+                if delivery.attempts >= MAX_ATTEMPTS {
+                    warn!(
+                        url = delivery.url,
+                        error = e,
+                        "Dropping webhook delivery after {MAX_ATTEMPTS} attempts"
+                    );
+                    remove_delivery(&path).await;
+                    continue;
+                }
+                delivery.next_attempt = now() + backoff(delivery.attempts).as_secs();
+                warn!(
+                    url = delivery.url,
+                    error = e,
+                    attempts = delivery.attempts,
+                    "Couldn't deliver webhook"
+                );
+                let data = serde_json::to_vec(&delivery)?;
+                if let Err(e) = write_delivery(&path, &data).await {
+                    warn!(error = ?e, path = %path.display(), "Couldn't update webhook delivery");
+                }
+                next = Some(next.map_or(delivery.next_attempt, |n| n.min(delivery.next_attempt)));
+            }
+        }
+    }
+    Ok(next)
+}
+
+async fn deliver(
+    client: &awc::Client,
+    webhook: &Webhook,
+    delivery: &Delivery,
+) -> Result<(), String> {
+    let res = client
+        .post(&delivery.url)
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("X-Uploader-Event", delivery.event.as_str()))
+        .insert_header((
+            "X-Uploader-Signature",
+            signature(&webhook.secret, &delivery.body),
+        ))
+        .send_body(delivery.body.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    match res.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("Status {}", res.status())),
+    }
+}
+
+/// The `X-Uploader-Signature` of a body, `sha256=<hex HMAC-SHA256>`.
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        <Hmac<Sha256>>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// 30s, 1m, 2m, ... up to 6h.
+fn backoff(attempts: u32) -> Duration {
+    Duration::from_secs(30)
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(Duration::from_secs(6 * 60 * 60))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_body() {
+        assert_eq!(
+            signature("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_ne!(signature("other", "{}"), signature("key", "{}"));
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(backoff(1), Duration::from_secs(30));
+        assert_eq!(backoff(2), Duration::from_secs(60));
+        assert_eq!(backoff(5), Duration::from_secs(8 * 60));
+        assert_eq!(backoff(10), Duration::from_secs(30 * 512));
+        assert_eq!(backoff(11), Duration::from_secs(6 * 60 * 60));
+        assert_eq!(backoff(u32::MAX), Duration::from_secs(6 * 60 * 60));
+    }
+
+    #[actix_web::test]
+    async fn queues_complete_deliveries() {
+        let dir = std::env::temp_dir().join(format!("uploader-webhooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let delivery = Delivery {
+            url: "https://example.com/hook".to_owned(),
+            event: WebhookEvent::Upload,
+            body: "{}".to_owned(),
+            attempts: 2,
+            next_attempt: 1,
+        };
+        let data = serde_json::to_vec(&delivery).unwrap();
+        let second = dir.join("000000000002-b.json");
+        write_delivery(&second, &data).await.unwrap();
+        write_delivery(&dir.join("000000000001-a.json"), &data)
+            .await
+            .unwrap();
+        // Another process is still writing this one.
+        std::fs::write(temp_path(&dir.join("000000000000-c.json")), b"{").unwrap();
+
+        let files = queued(&dir).await.unwrap();
+        let names = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["000000000001-a.json", "000000000002-b.json"]);
+        let read: Delivery = serde_json::from_slice(&std::fs::read(&second).unwrap()).unwrap();
+        assert_eq!((read.attempts, read.next_attempt), (2, 1));
+
+        remove_delivery(&second).await;
+        // Already removed, which isn't an error.
+        remove_delivery(&second).await;
+        assert_eq!(queued(&dir).await.unwrap().len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
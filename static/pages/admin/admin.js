@@ -0,0 +1,224 @@
+// Shared with the home page.
+const LOCAL_STORAGE_KEY = 'uploader-authorization';
+const PER_PAGE = 48;
+
+(function main() {
+  const view = document.getElementById('view');
+  const saveForm = document.getElementById('set-auth-form');
+  const authInput = document.getElementById('auth-input');
+  const clearAuth = document.getElementById('clear-auth');
+  const errorMessageEl = document.getElementById('error-message');
+  const filterForm = document.getElementById('filter-form');
+  const prevPage = document.getElementById('prev-page');
+  const nextPage = document.getElementById('next-page');
+
+  let page = 1;
+  let filters = new URLSearchParams();
+
+  const showError = e => {
+    errorMessageEl.textContent = e?.message ?? String(e);
+  };
+
+  const refresh = () => {
+    errorMessageEl.textContent = '';
+    loadStats().catch(showError);
+    loadUploads().catch(showError);
+  };
+
+  const deleteUpload = async upload => {
+    const res = await api('/api/admin/uploads', {
+      method: 'DELETE',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ files: [upload.name] }),
+    });
+    const error = res.errors[upload.name];
+    if (error) {
+      throw new Error(`${upload.name}: ${error}`);
+    }
+    refresh();
+  };
+
+  const loadUploads = async () => {
+    const query = new URLSearchParams(filters);
+    query.set('page', page);
+    query.set('per_page', PER_PAGE);
+    const list = await api(`/api/admin/uploads?${query}`);
+    renderUploads(list.uploads, deleteUpload, showError);
+
+    const pages = Math.max(1, Math.ceil(list.total / list.per_page));
+    document.getElementById('page-label').textContent =
+      `Page ${list.page} of ${pages} (${list.total} files)`;
+    prevPage.disabled = list.page <= 1;
+    nextPage.disabled = list.page >= pages;
+  };
+
+  const updateState = () => {
+    if (localStorage.getItem(LOCAL_STORAGE_KEY)) {
+      view.classList.add('authenticated');
+      refresh();
+    } else {
+      view.classList.remove('authenticated');
+    }
+  };
+
+  saveForm.addEventListener('submit', e => {
+    e.preventDefault();
+    if (authInput.value) {
+      localStorage.setItem(LOCAL_STORAGE_KEY, authInput.value);
+    }
+    updateState();
+  });
+  clearAuth.addEventListener('click', () => {
+    localStorage.removeItem(LOCAL_STORAGE_KEY);
+    updateState();
+  });
+  filterForm.addEventListener('submit', e => {
+    e.preventDefault();
+    filters = new URLSearchParams();
+    for (const [key, value] of new FormData(filterForm)) {
+      if (value) {
+        filters.set(key, value);
+      }
+    }
+    page = 1;
+    loadUploads().catch(showError);
+  });
+  prevPage.addEventListener('click', () => {
+    page--;
+    loadUploads().catch(showError);
+  });
+  nextPage.addEventListener('click', () => {
+    page++;
+    loadUploads().catch(showError);
+  });
+
+  updateState();
+})();
+
+async function api(path, init = {}) {
+  const res = await fetch(path, {
+    ...init,
+    headers: { ...init.headers, Authorization: localStorage.getItem(LOCAL_STORAGE_KEY) },
+  });
+  const json = await res.json();
+  if (!res.ok) {
+    throw new Error(`${res.status} - ${json?.error ?? json?.message ?? JSON.stringify(json)}`);
+  }
+  return json;
+}
+
+async function loadStats() {
+  const stats = await api('/api/admin/stats');
+  document.getElementById('stat-files').textContent = stats.files;
+  document.getElementById('stat-size').textContent = formatSize(stats.size);
+  document.getElementById('stat-oldest').textContent = stats.oldest ? formatDate(stats.oldest) : '-';
+  document.getElementById('stat-retention').textContent =
+    `${Math.round(stats.retention.max_age / (60 * 60 * 24))} days`;
+  document.getElementById('stat-expired').textContent =
+    `${stats.retention.expired_files} files (${formatSize(stats.retention.expired_size)}) ` +
+    'will be removed by the next clean';
+
+  const tbody = document.querySelector('#keys > tbody');
+  const uploaders = document.getElementById('uploaders');
+  tbody.replaceChildren();
+  uploaders.replaceChildren();
+  for (const key of stats.keys) {
+    const row = document.createElement('tr');
+    for (const text of [
+      key.name ?? '(unknown)',
+      key.files,
+      formatSize(key.size),
+      formatDate(key.last_upload),
+    ]) {
+      const cell = document.createElement('td');
+      cell.textContent = text;
+      row.append(cell);
+    }
+    tbody.append(row);
+
+    if (key.name) {
+      const option = document.createElement('option');
+      option.value = key.name;
+      uploaders.append(option);
+    }
+  }
+}
+
+function renderUploads(uploads, onDelete, onError) {
+  const grid = document.getElementById('uploads');
+  grid.replaceChildren();
+  for (const upload of uploads) {
+    const card = document.createElement('div');
+    card.classList.add('upload');
+    card.append(makePreview(upload));
+
+    const info = document.createElement('div');
+    info.classList.add('upload-info');
+    const link = document.createElement('a');
+    link.href = upload.link;
+    link.target = '_blank';
+    link.rel = 'noopener';
+    link.textContent = upload.name;
+    const details = document.createElement('span');
+    details.textContent = `${formatSize(upload.size)} - ${formatDate(upload.modified)}`;
+    const uploader = document.createElement('span');
+    uploader.textContent = upload.uploader ?? '(unknown)';
+    info.append(link, details, uploader);
+    card.append(info);
+
+    const deleteBtn = document.createElement('button');
+    deleteBtn.classList.add('icon-button', 'col-red');
+    deleteBtn.textContent = 'Delete';
+    deleteBtn.addEventListener('click', () => {
+      deleteBtn.disabled = true;
+      onDelete(upload).catch(e => {
+        deleteBtn.disabled = false;
+        onError(e);
+      });
+    });
+    card.append(deleteBtn);
+
+    grid.append(card);
+  }
+}
+
+function makePreview(upload) {
+  const preview = document.createElement('div');
+  preview.classList.add('preview');
+  const [type] = upload.content_type.split('/');
+  let media = null;
+  if (type === 'image') {
+    media = document.createElement('img');
+    media.loading = 'lazy';
+    media.alt = upload.name;
+  } else if (type === 'video') {
+    media = document.createElement('video');
+    media.muted = true;
+    media.preload = 'metadata';
+  } else if (type === 'audio') {
+    media = document.createElement('audio');
+    media.controls = true;
+    media.preload = 'none';
+  }
+  if (media) {
+    media.src = upload.link;
+    preview.append(media);
+  } else {
+    preview.textContent = upload.name.split('.').pop();
+  }
+  return preview;
+}
+
+function formatSize(bytes) {
+  const units = ['B', 'KiB', 'MiB', 'GiB', 'TiB'];
+  let unit = 0;
+  while (bytes >= 1024 && unit < units.length - 1) {
+    bytes /= 1024;
+    unit++;
+  }
+  return `${unit === 0 ? bytes : bytes.toFixed(1)} ${units[unit]}`;
+}
+
+function formatDate(timestamp) {
+  return new Date(timestamp * 1000).toLocaleString();
+}
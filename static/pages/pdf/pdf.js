@@ -0,0 +1,17 @@
+(function main() {
+  const copyURL = document.getElementById('copy-url');
+
+  copyURL.addEventListener('click', () => {
+    navigator.clipboard
+      .writeText(location.href)
+      .then(() =>
+        copyURL.animate(
+          {
+            transform: ['scale(1.05)', 'scale(1)'],
+          },
+          { duration: 150 },
+        ),
+      )
+      .catch(console.error);
+  });
+})();
@@ -1,20 +1,13 @@
 const AUTO_HIGHLIGHT_KEY = 'uploader-autohighlight';
 
-function getFilename() {
-  const idx = location.pathname.lastIndexOf('/');
-  if (idx === -1) {
-    return null;
-  }
-  return location.pathname.substring(idx);
-}
-
 (async function main() {
-  // starts with '/'
-  const file = getFilename();
+  const view = document.getElementById('view');
+  // either '/{name}' or the file on the user content domain
+  const file = view.dataset.file;
   const notFound = () => {
     location.href = location.origin + '/static/404.html';
   };
-  if (!file || file.length < 2) {
+  if (!file) {
     notFound();
     return;
   }
@@ -23,8 +16,7 @@ function getFilename() {
     notFound();
     return;
   }
-  const text = await res.text();
-  const view = document.getElementById('view');
+  const text = await decodeText(res);
   const textView = document.getElementById('text-view');
   textView.textContent = text;
 
@@ -48,7 +40,7 @@ function getFilename() {
   downloadBtn.addEventListener('click', () => {
     const a = document.createElement('a');
     a.href = URL.createObjectURL(new Blob([text]));
-    a.download = file.substring(1);
+    a.download = file.substring(file.lastIndexOf('/') + 1);
     a.click();
   });
   view.classList.remove('loading');
@@ -68,6 +60,23 @@ function getFilename() {
   }
 })();
 
+/**
+ * Decodes the body using the charset from the Content-Type (`res.text()` always uses UTF-8).
+ *
+ * @param {Response} res
+ * @return {Promise<string>}
+ */
+async function decodeText(res) {
+  const buf = await res.arrayBuffer();
+  const charset = /;\s*charset="?([^";]+)"?/i.exec(res.headers.get('content-type') ?? '')?.[1];
+  try {
+    return new TextDecoder(charset ?? 'utf-8').decode(buf);
+  } catch (e) {
+    console.warn('Unsupported charset', charset, e);
+    return new TextDecoder().decode(buf);
+  }
+}
+
 async function highlightText({ textView, view }) {
   const STYLESHEET_URL = '/static/lib/hljs-github-dark.min.css';
   try {